@@ -0,0 +1,8 @@
+//! Just the engine, so the benchmark doesn't need JACK. Most of it goes unused here.
+
+#![allow(dead_code, unused_imports)]
+
+#[path = "../../src/audio/bitset.rs"]
+mod bitset;
+#[path = "../../src/audio/engine/mod.rs"]
+pub mod engine;
@@ -0,0 +1,198 @@
+//! Compares the stack's slot-based ports with the design they replaced, where every port
+//! owned a buffer that its connection was copied into each block. Both run the same nodes, so
+//! the difference is the copying. Run it with `cargo bench --bench stack`.
+
+use std::time::{Duration, Instant};
+
+use arrayvec::ArrayVec;
+use basedrop::{Collector, Owned};
+
+mod audio;
+
+use audio::engine::*;
+
+const SAMPLE_RATE: usize = 48000;
+const SECONDS: usize = 20;
+/// Each design's time is the best of this many renders.
+const RUNS: usize = 5;
+
+/// Four groups of an oscillator, `Abs`, `Mul` and `Add`, each summed into the output.
+fn chain() -> Vec<Node> {
+    let kind = |name| NodeKind::named(name).unwrap();
+    let mut nodes = Vec::new();
+    for group in 0..4u8 {
+        let slot = Some(Slot::Audio(group + 1));
+        let mut oscillator = Node::new(kind("Oscillator"));
+        let frequency = oscillator.inputs.named_mut("frequency").unwrap();
+        *frequency = frequency.with_value(110.0 * (group + 1) as f32);
+        oscillator.outputs[0].slot = slot;
+        let mut abs = Node::new(kind("Abs"));
+        abs.inputs[0].slot = slot;
+        abs.outputs[0].slot = slot;
+        let mut mul = Node::new(kind("Mul"));
+        mul.inputs[0].slot = slot;
+        mul.inputs[1] = mul.inputs[1].with_value(0.25);
+        mul.outputs[0].slot = slot;
+        let mut add = Node::new(kind("Add"));
+        add.inputs[0].slot = slot;
+        add.inputs[1].slot = Some(Slot::Audio(0));
+        add.outputs[0].slot = Some(Slot::Audio(0));
+        nodes.extend([oscillator, abs, mul, add]);
+    }
+    nodes
+}
+
+/// What the old design cost, and no more: before a node runs, each of its connected inputs
+/// is copied into a buffer of its own, and afterwards each output is copied out to the slot
+/// it's connected to.
+struct CopyPerPort {
+    nodes: Vec<CopyingNode>,
+    data: StackData,
+    /// The nodes' own buffers, which their ports are connected to instead of the shared slots.
+    own: StackData,
+}
+
+struct CopyingNode {
+    node: Node,
+    /// Shared and own audio slots, for each connected port.
+    inputs: Vec<(usize, usize)>,
+    outputs: Vec<(usize, usize)>,
+    /// Control slots the node reads, which are copied across as they are.
+    controls: Vec<usize>,
+}
+
+impl CopyPerPort {
+    fn new(nodes: Vec<Node>, block_size: usize) -> CopyPerPort {
+        let nodes = nodes
+            .into_iter()
+            .map(|mut node| {
+                let (mut inputs, mut outputs, mut controls) = (Vec::new(), Vec::new(), Vec::new());
+                let mut own = 1;
+                for port in node.inputs.iter_mut() {
+                    match port.slot {
+                        Some(Slot::Audio(shared)) => {
+                            inputs.push((shared as usize, own as usize));
+                            port.slot = Some(Slot::Audio(own));
+                            own += 1;
+                        }
+                        Some(Slot::Control(index)) => controls.push(index as usize),
+                        None => {}
+                    }
+                }
+                for port in node.outputs.iter_mut() {
+                    if let Some(Slot::Audio(shared)) = port.slot {
+                        outputs.push((shared as usize, own as usize));
+                        port.slot = Some(Slot::Audio(own));
+                        own += 1;
+                    }
+                }
+                CopyingNode {
+                    node,
+                    inputs,
+                    outputs,
+                    controls,
+                }
+            })
+            .collect();
+        CopyPerPort {
+            nodes,
+            data: StackData::new(block_size),
+            own: StackData::new(block_size),
+        }
+    }
+}
+
+/// The two designs, for `time`.
+trait Design {
+    fn reset(&mut self);
+    fn process(&mut self, output_buffer: &mut [f32]);
+}
+
+impl Design for Stack {
+    fn reset(&mut self) {
+        Stack::reset(self);
+    }
+
+    fn process(&mut self, output_buffer: &mut [f32]) {
+        Stack::process(self, output_buffer, SAMPLE_RATE);
+    }
+}
+
+impl Design for CopyPerPort {
+    fn process(&mut self, output_buffer: &mut [f32]) {
+        for chunk in output_buffer.chunks_mut(self.data.block_size()) {
+            let samples = chunk.len();
+            for node in &mut self.nodes {
+                for &(shared, own) in &node.inputs {
+                    self.own.audio[own][..samples]
+                        .copy_from_slice(&self.data.audio[shared][..samples]);
+                }
+                for &index in &node.controls {
+                    self.own.control[index] = self.data.control[index];
+                }
+                node.node.process(samples, &mut self.own, SAMPLE_RATE);
+                for &(shared, own) in &node.outputs {
+                    self.data.audio[shared][..samples]
+                        .copy_from_slice(&self.own.audio[own][..samples]);
+                }
+            }
+            for (output, sample) in chunk.iter_mut().zip(&*self.data.audio[0]) {
+                *output += sample;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.data.clear();
+        self.own.clear();
+        for node in &mut self.nodes {
+            node.node.reset();
+        }
+    }
+}
+
+/// Renders `SECONDS` of audio `RUNS` times, returning the quickest time and the last render.
+fn time(design: &mut impl Design, block_size: usize) -> (Duration, Vec<f32>) {
+    let mut output = vec![0.0; SECONDS * SAMPLE_RATE];
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        design.reset();
+        output.fill(0.0);
+        let start = Instant::now();
+        for block in output.chunks_mut(block_size) {
+            design.process(block);
+        }
+        best = best.min(start.elapsed());
+    }
+    (best, output)
+}
+
+fn main() {
+    let collector = Collector::new();
+    let handle = collector.handle();
+    println!(
+        "{} s through {} nodes, best of {}:",
+        SECONDS,
+        chain().len(),
+        RUNS
+    );
+    println!("{:>16}{:>16}{:>16}", "", "copy per port", "slots");
+    for block_size in [64, 256, 1024] {
+        let nodes: ArrayVec<Owned<Node>, MAX_NODES> = chain()
+            .into_iter()
+            .map(|node| Owned::new(&handle, node))
+            .collect();
+        let mut stack = Stack::new(Owned::new(&handle, nodes), block_size);
+        let mut copying = CopyPerPort::new(chain(), block_size);
+        let (copy_time, copy_output) = time(&mut copying, block_size);
+        let (slot_time, slot_output) = time(&mut stack, block_size);
+        assert!(copy_output == slot_output, "the designs disagree");
+        let ms = |time: Duration| format!("{:.1} ms", time.as_secs_f64() * 1000.0);
+        println!(
+            "{:>16}{:>16}{:>16}",
+            format!("{} frames", block_size),
+            ms(copy_time),
+            ms(slot_time)
+        );
+    }
+}
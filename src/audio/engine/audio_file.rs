@@ -0,0 +1,78 @@
+use std::{any::Any, fmt, ops::Deref, path::Path};
+
+use basedrop::{Handle, Shared};
+
+/// A sound loaded from a WAV file, mixed down to mono.
+pub struct AudioFile {
+    pub name: String,
+    pub sample_rate: f32,
+    pub frames: Vec<f32>,
+    /// Whatever the node it's for built from `frames` while loading, like a wavetable's
+    /// mip-maps. See `NodeProcessor::prepare_audio`.
+    pub prepared: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl AudioFile {
+    /// Reads and decodes the whole file, so this belongs on a thread of its own.
+    pub fn load(path: &Path) -> Result<AudioFile, hound::Error> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let full_scale = (1u32 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 / full_scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        let channels = spec.channels.max(1) as usize;
+        let frames = samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(AudioFile {
+            name,
+            sample_rate: spec.sample_rate as f32,
+            frames,
+            prepared: None,
+        })
+    }
+}
+
+/// An `AudioFile` shared between the UI's copy of a node and the engine's. Whichever lets go
+/// last hands it to the collector, so dropping one on the audio thread doesn't free anything
+/// there. Two are equal when they're the same load.
+#[derive(Clone)]
+pub struct SharedAudio(Shared<AudioFile>);
+
+impl SharedAudio {
+    pub fn new(collector: &Handle, file: AudioFile) -> SharedAudio {
+        SharedAudio(Shared::new(collector, file))
+    }
+}
+
+impl Deref for SharedAudio {
+    type Target = AudioFile;
+
+    fn deref(&self) -> &AudioFile {
+        &self.0
+    }
+}
+
+impl PartialEq for SharedAudio {
+    fn eq(&self, other: &SharedAudio) -> bool {
+        std::ptr::eq::<AudioFile>(&**self, &**other)
+    }
+}
+
+impl fmt::Debug for SharedAudio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({} frames)", self.name, self.frames.len())
+    }
+}
@@ -0,0 +1,16 @@
+//! Building blocks shared between node processors.
+
+mod blep;
+mod delay;
+mod fft;
+mod filter;
+mod oversample;
+mod phasor;
+mod random;
+pub use blep::*;
+pub use delay::*;
+pub use fft::*;
+pub use filter::*;
+pub use oversample::*;
+pub use phasor::*;
+pub use random::*;
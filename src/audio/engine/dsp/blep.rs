@@ -0,0 +1,29 @@
+//! Polynomial corrections for band-limiting discontinuities. `phase` is where a waveform is
+//! in its cycle, from 0 to 1, and `increment` is how far it moves each sample; a
+//! discontinuity is assumed to sit at phase 0.
+
+/// Smooths a step of height 2, from -1 to 1. Subtract it to smooth a downward jump.
+pub fn poly_blep(phase: f32, increment: f32) -> f32 {
+    if phase < increment {
+        let t = phase / increment;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - increment {
+        let t = (phase - 1.0) / increment;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Smooths a corner where the slope increases by 2 per sample, to match `poly_blep`.
+pub fn poly_blamp(phase: f32, increment: f32) -> f32 {
+    if phase < increment {
+        let t = phase / increment - 1.0;
+        -t * t * t / 3.0
+    } else if phase > 1.0 - increment {
+        let t = (phase - 1.0) / increment + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,114 @@
+/// Delay lines are sized up front for this rate. Above it, the longest delays are cut short.
+pub const MAX_SAMPLE_RATE: f32 = 96000.0;
+
+/// A circular buffer of past samples, read at fractional delays with cubic interpolation.
+/// The buffer is allocated up front, so nothing here allocates once it's built.
+#[derive(Clone, Debug)]
+pub struct DelayLine {
+    buffer: Box<[f32]>,
+    /// Where the next sample goes.
+    write: usize,
+}
+
+impl DelayLine {
+    /// The shortest delay `read` gives, since interpolating needs a sample either side.
+    pub const MIN_DELAY: f32 = 3.0;
+
+    pub fn new(length: usize) -> DelayLine {
+        DelayLine {
+            buffer: vec![0.0; length.max(4)].into_boxed_slice(),
+            write: 0,
+        }
+    }
+
+    /// A line that holds `seconds` at up to `MAX_SAMPLE_RATE`.
+    pub fn with_seconds(seconds: f32) -> DelayLine {
+        DelayLine::new((seconds * MAX_SAMPLE_RATE) as usize + 4)
+    }
+
+    /// The longest delay `read` gives.
+    pub fn max_delay(&self) -> f32 {
+        (self.buffer.len() - 2) as f32
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.buffer[self.write] = sample;
+        self.write = (self.write + 1) % self.buffer.len();
+    }
+
+    /// The sample pushed `delay` samples ago, where 1 is the latest one.
+    pub fn tap(&self, delay: usize) -> f32 {
+        let len = self.buffer.len();
+        self.buffer[(self.write + len - delay.clamp(1, len)) % len]
+    }
+
+    /// The signal `delay` samples ago, interpolated between samples. A delay that isn't
+    /// finite reads as the shortest one.
+    pub fn read(&self, delay: f32) -> f32 {
+        let delay = if delay.is_finite() {
+            delay
+        } else {
+            DelayLine::MIN_DELAY
+        };
+        let delay = delay.clamp(DelayLine::MIN_DELAY, self.max_delay());
+        let whole = delay.ceil();
+        let fraction = whole - delay;
+        let whole = whole as usize;
+        // Oldest to newest, with the point we want between `x0` and `x1`.
+        let (xm1, x0) = (self.tap(whole + 1), self.tap(whole));
+        let (x1, x2) = (self.tap(whole - 1), self.tap(whole - 2));
+        let c1 = 0.5 * (x1 - xm1);
+        let c2 = xm1 - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+        let c3 = 0.5 * (x2 - xm1) + 1.5 * (x0 - x1);
+        ((c3 * fraction + c2) * fraction + c1) * fraction + x0
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill(0.0);
+        self.write = 0;
+    }
+}
+
+/// A Schroeder all-pass around a delay line. It smears a signal out in time without changing
+/// its spectrum.
+#[derive(Clone, Debug)]
+pub struct Allpass {
+    line: DelayLine,
+}
+
+impl Allpass {
+    pub fn new(length: usize) -> Allpass {
+        Allpass {
+            line: DelayLine::new(length),
+        }
+    }
+
+    /// `delay` is in samples, and `gain` between -1 and 1.
+    pub fn process(&mut self, input: f32, delay: f32, gain: f32) -> f32 {
+        let delayed = self.line.read(delay);
+        let v = input + gain * delayed;
+        self.line.push(v);
+        delayed - gain * v
+    }
+
+    pub fn clear(&mut self) {
+        self.line.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_delays_read_the_shortest_one() {
+        let mut line = DelayLine::new(64);
+        for i in 0..64 {
+            line.push(i as f32);
+        }
+        let shortest = line.read(DelayLine::MIN_DELAY);
+        for delay in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(line.read(delay), shortest, "{}", delay);
+        }
+    }
+}
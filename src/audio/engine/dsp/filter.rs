@@ -0,0 +1,64 @@
+use std::f32::consts::PI;
+
+/// The integrator gain for a zero-delay-feedback filter with this cutoff, kept clear of
+/// Nyquist so it stays finite.
+pub fn prewarp(cutoff: f32, sample_rate: f32) -> f32 {
+    let cutoff = cutoff.clamp(1.0, sample_rate * 0.49);
+    (PI * cutoff / sample_rate).tan()
+}
+
+/// A trapezoidal one-pole low-pass. It only keeps the integrator's state, so its cutoff can
+/// change every sample.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OnePole {
+    pub state: f32,
+}
+
+impl OnePole {
+    /// How much the output depends on the input this sample, given `prewarp`'s `g`.
+    pub fn feedthrough(g: f32) -> f32 {
+        g / (1.0 + g)
+    }
+
+    /// The part of the output that only depends on the state.
+    pub fn residue(&self, feedthrough: f32) -> f32 {
+        (1.0 - feedthrough) * self.state
+    }
+
+    pub fn low_pass(&mut self, input: f32, feedthrough: f32) -> f32 {
+        let v = (input - self.state) * feedthrough;
+        let output = v + self.state;
+        self.state = output + v;
+        output
+    }
+}
+
+/// A first-order all-pass: flat in level, with its phase shift passing 90 degrees at the
+/// frequency given to `coefficient`. Several in a row make a phaser.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FirstOrderAllpass {
+    state: f32,
+}
+
+impl FirstOrderAllpass {
+    pub fn coefficient(frequency: f32, sample_rate: f32) -> f32 {
+        let g = prewarp(frequency, sample_rate);
+        (g - 1.0) / (g + 1.0)
+    }
+
+    pub fn process(&mut self, input: f32, coefficient: f32) -> f32 {
+        let output = coefficient * input + self.state;
+        self.state = input - coefficient * output;
+        output
+    }
+}
+
+/// Rounds values too small to hear down to zero. Feedback loops that die away otherwise end
+/// up on denormal numbers, which are very slow on some CPUs.
+pub fn flush_denormal(value: f32) -> f32 {
+    if value.abs() < 1e-15 {
+        0.0
+    } else {
+        value
+    }
+}
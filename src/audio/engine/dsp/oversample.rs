@@ -0,0 +1,96 @@
+use std::f32::consts::PI;
+
+const TAPS: usize = 31;
+
+/// A windowed-sinc low-pass at a quarter of its sample rate, which is the original Nyquist
+/// frequency once the rate has been doubled.
+#[derive(Clone, Copy, Debug)]
+struct HalfBand {
+    coefficients: [f32; TAPS],
+    history: [f32; TAPS],
+    position: usize,
+}
+
+impl HalfBand {
+    fn new() -> HalfBand {
+        let middle = (TAPS - 1) as f32 / 2.0;
+        let mut coefficients = [0.0; TAPS];
+        for (n, coefficient) in coefficients.iter_mut().enumerate() {
+            let t = n as f32 - middle;
+            let sinc = if t == 0.0 {
+                1.0
+            } else {
+                (0.5 * PI * t).sin() / (0.5 * PI * t)
+            };
+            let phase = 2.0 * PI * n as f32 / (TAPS - 1) as f32;
+            let blackman = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+            *coefficient = sinc * blackman;
+        }
+        let sum: f32 = coefficients.iter().sum();
+        HalfBand {
+            coefficients: coefficients.map(|coefficient| coefficient / sum),
+            history: [0.0; TAPS],
+            position: 0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) -> f32 {
+        self.history[self.position] = sample;
+        self.position = (self.position + 1) % TAPS;
+        let (older, newer) = self.history.split_at(self.position);
+        newer
+            .iter()
+            .chain(older)
+            .zip(&self.coefficients)
+            .map(|(sample, coefficient)| sample * coefficient)
+            .sum()
+    }
+}
+
+/// Runs a nonlinearity at a multiple of the sample rate, filtering on the way up and down so
+/// the harmonics it adds above Nyquist are removed instead of folding back down. Each
+/// doubling delays the signal by a few samples.
+#[derive(Clone, Copy, Debug)]
+pub struct Oversampler {
+    up: [HalfBand; 2],
+    down: [HalfBand; 2],
+}
+
+impl Oversampler {
+    /// Doublings available, so the highest factor is 4.
+    pub const MAX_DOUBLINGS: usize = 2;
+
+    /// Passes `input` through `shape` at 2^`doublings` times the sample rate.
+    pub fn process(
+        &mut self,
+        input: f32,
+        doublings: usize,
+        shape: &mut impl FnMut(f32) -> f32,
+    ) -> f32 {
+        let doublings = doublings.min(Oversampler::MAX_DOUBLINGS);
+        if doublings == 0 {
+            return shape(input);
+        }
+        let stage = doublings - 1;
+        // Zero-stuffing halves the level, so make up for it.
+        let first = self.up[stage].push(input * 2.0);
+        let second = self.up[stage].push(0.0);
+        let first = self.process(first, stage, shape);
+        let second = self.process(second, stage, shape);
+        self.down[stage].push(first);
+        self.down[stage].push(second)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Oversampler::default();
+    }
+}
+
+impl Default for Oversampler {
+    fn default() -> Oversampler {
+        Oversampler {
+            up: [HalfBand::new(); 2],
+            down: [HalfBand::new(); 2],
+        }
+    }
+}
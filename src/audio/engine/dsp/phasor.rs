@@ -0,0 +1,23 @@
+use std::f32::consts::TAU;
+
+/// A phase that runs from 0 to 1 and wraps, for effects that modulate themselves.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Phasor {
+    pub phase: f32,
+}
+
+impl Phasor {
+    /// Moves on a sample at `frequency`. A frequency that isn't finite holds the phase rather
+    /// than leaving it NaN for good.
+    pub fn advance(&mut self, frequency: f32, sample_rate: f32) {
+        let increment = frequency / sample_rate;
+        if increment.is_finite() {
+            self.phase = (self.phase + increment).rem_euclid(1.0);
+        }
+    }
+
+    /// A sine at the current phase plus `offset` cycles.
+    pub fn sine(&self, offset: f32) -> f32 {
+        ((self.phase + offset) * TAU).sin()
+    }
+}
@@ -0,0 +1,42 @@
+/// A small xorshift generator. The same seed always gives the same numbers, so renders can
+/// be repeated exactly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Random {
+        // Xorshift gets stuck at zero.
+        Random { state: seed.max(1) }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Uniform between -1 and 1.
+    pub fn next_bipolar(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+
+    /// The state split into two exactly representable halves, for saving.
+    pub fn save(&self) -> [f32; 2] {
+        [(self.state >> 16) as f32, (self.state & 0xffff) as f32]
+    }
+
+    pub fn restore(&mut self, [high, low]: [f32; 2]) {
+        *self = Random::new((high as u32) << 16 | low as u32);
+    }
+}
+
+impl Default for Random {
+    fn default() -> Random {
+        Random::new(1)
+    }
+}
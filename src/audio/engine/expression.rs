@@ -0,0 +1,465 @@
+use std::{fmt, ops::Deref};
+
+use arrayvec::ArrayVec;
+use basedrop::{Handle, Shared};
+
+/// Names an expression can read, in the order of the expression node's inputs.
+pub const VARIABLES: [&str; 4] = ["a", "b", "c", "d"];
+
+/// The deepest a program's stack can get. Compiling anything that needs more fails, so
+/// evaluating never has to allocate.
+const MAX_STACK: usize = 32;
+
+/// How deeply parentheses, calls, negations and powers can nest. The compiler recurses for
+/// each level, so this keeps a long run of them from overflowing the UI thread's stack.
+const MAX_NESTING: usize = 64;
+
+const CONSTANTS: [(&str, f32); 3] = [
+    ("pi", std::f32::consts::PI),
+    ("tau", std::f32::consts::TAU),
+    ("e", std::f32::consts::E),
+];
+
+const FUNCTIONS: [(&str, Function); 13] = [
+    ("sin", Function::Unary(f32::sin)),
+    ("cos", Function::Unary(f32::cos)),
+    ("tan", Function::Unary(f32::tan)),
+    ("tanh", Function::Unary(f32::tanh)),
+    ("abs", Function::Unary(f32::abs)),
+    ("sqrt", Function::Unary(f32::sqrt)),
+    ("exp", Function::Unary(f32::exp)),
+    ("ln", Function::Unary(f32::ln)),
+    ("floor", Function::Unary(f32::floor)),
+    ("fract", Function::Unary(f32::fract)),
+    ("min", Function::Binary(f32::min)),
+    ("max", Function::Binary(f32::max)),
+    ("pow", Function::Binary(f32::powf)),
+];
+
+#[derive(Clone, Copy)]
+enum Function {
+    Unary(fn(f32) -> f32),
+    Binary(fn(f32, f32) -> f32),
+}
+
+impl Function {
+    fn arguments(self) -> usize {
+        match self {
+            Function::Unary(_) => 1,
+            Function::Binary(_) => 2,
+        }
+    }
+}
+
+/// One step of a compiled expression, working on a stack of values.
+#[derive(Clone, Copy)]
+enum Op {
+    Constant(f32),
+    Variable(u8),
+    Negate,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+    Unary(fn(f32) -> f32),
+    Binary(fn(f32, f32) -> f32),
+}
+
+/// A formula over `VARIABLES`, like `sin(a * tau) * b + c`. It has the usual arithmetic
+/// operators, `^` for powers, a few constants and functions, and parentheses.
+pub struct Program {
+    pub source: String,
+    ops: Vec<Op>,
+}
+
+impl Program {
+    /// Parses and compiles `source`. This allocates, so it's for the UI thread.
+    pub fn compile(source: &str) -> Result<Program, ExpressionError> {
+        let mut compiler = Compiler {
+            source,
+            position: 0,
+            ops: Vec::new(),
+            depth: 0,
+            nesting: 0,
+        };
+        compiler.expression()?;
+        compiler.skip_whitespace();
+        if compiler.position < source.len() {
+            return Err(compiler.unexpected());
+        }
+        Ok(Program {
+            source: source.to_owned(),
+            ops: compiler.ops,
+        })
+    }
+
+    /// Runs the program with `variables` standing in for `VARIABLES`.
+    pub fn evaluate(&self, variables: &[f32; VARIABLES.len()]) -> f32 {
+        let mut stack = ArrayVec::<f32, MAX_STACK>::new();
+        for &op in &self.ops {
+            let value = match op {
+                Op::Constant(value) => value,
+                Op::Variable(index) => variables[index as usize],
+                Op::Negate => -stack.pop().unwrap(),
+                Op::Unary(function) => function(stack.pop().unwrap()),
+                _ => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    match op {
+                        Op::Add => left + right,
+                        Op::Subtract => left - right,
+                        Op::Multiply => left * right,
+                        Op::Divide => left / right,
+                        Op::Remainder => left % right,
+                        Op::Power => left.powf(right),
+                        Op::Binary(function) => function(left, right),
+                        _ => unreachable!(),
+                    }
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().unwrap_or(0.0)
+    }
+}
+
+/// A recursive descent parser that emits ops as it goes, keeping track of how deep the stack
+/// will get.
+struct Compiler<'a> {
+    source: &'a str,
+    position: usize,
+    ops: Vec<Op>,
+    depth: usize,
+    /// How many factors are being parsed inside each other.
+    nesting: usize,
+}
+
+impl<'a> Compiler<'a> {
+    /// Sums and differences of terms.
+    fn expression(&mut self) -> Result<(), ExpressionError> {
+        self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => Op::Add,
+                Some('-') => Op::Subtract,
+                _ => return Ok(()),
+            };
+            self.position += 1;
+            self.term()?;
+            self.emit(op, 2)?;
+        }
+    }
+
+    /// Products, quotients and remainders of factors.
+    fn term(&mut self) -> Result<(), ExpressionError> {
+        self.factor()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => Op::Multiply,
+                Some('/') => Op::Divide,
+                Some('%') => Op::Remainder,
+                _ => return Ok(()),
+            };
+            self.position += 1;
+            self.factor()?;
+            self.emit(op, 2)?;
+        }
+    }
+
+    /// A negated factor, or a power. `-a ^ 2` is `-(a ^ 2)`, and powers group to the right.
+    /// Every kind of nesting goes through here, so this is where it's limited.
+    fn factor(&mut self) -> Result<(), ExpressionError> {
+        if self.nesting == MAX_NESTING {
+            return Err(ExpressionError {
+                position: self.position,
+                kind: ErrorKind::TooComplex,
+            });
+        }
+        self.nesting += 1;
+        let result = self.nested_factor();
+        self.nesting -= 1;
+        result
+    }
+
+    fn nested_factor(&mut self) -> Result<(), ExpressionError> {
+        if self.peek() == Some('-') {
+            self.position += 1;
+            self.factor()?;
+            return self.emit(Op::Negate, 1);
+        }
+        self.atom()?;
+        if self.peek() == Some('^') {
+            self.position += 1;
+            self.factor()?;
+            self.emit(Op::Power, 2)?;
+        }
+        Ok(())
+    }
+
+    /// A number, a name, a function call or a parenthesised expression.
+    fn atom(&mut self) -> Result<(), ExpressionError> {
+        let start = self.position;
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                self.expression()?;
+                self.expect(')')
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = self.take_while(|c| c.is_ascii_digit() || c == '.');
+                let value = number.parse().map_err(|_| ExpressionError {
+                    position: start,
+                    kind: ErrorKind::BadNumber(number.to_owned()),
+                })?;
+                self.emit(Op::Constant(value), 0)
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                if self.peek() == Some('(') {
+                    self.position += 1;
+                    self.call(name, start)
+                } else if let Some(index) = VARIABLES.iter().position(|&v| v == name) {
+                    self.emit(Op::Variable(index as u8), 0)
+                } else if let Some(&(_, value)) = CONSTANTS.iter().find(|(c, _)| *c == name) {
+                    self.emit(Op::Constant(value), 0)
+                } else {
+                    Err(ExpressionError {
+                        position: start,
+                        kind: ErrorKind::UnknownName(name.to_owned()),
+                    })
+                }
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// The arguments and closing parenthesis of a call to `name`, which started at `start`.
+    fn call(&mut self, name: &str, start: usize) -> Result<(), ExpressionError> {
+        let function = FUNCTIONS
+            .iter()
+            .find(|(f, _)| *f == name)
+            .map(|&(_, function)| function)
+            .ok_or_else(|| ExpressionError {
+                position: start,
+                kind: ErrorKind::UnknownFunction(name.to_owned()),
+            })?;
+        let mut arguments = 0;
+        if self.peek() != Some(')') {
+            loop {
+                self.expression()?;
+                arguments += 1;
+                if self.peek() != Some(',') {
+                    break;
+                }
+                self.position += 1;
+            }
+        }
+        self.expect(')')?;
+        if arguments != function.arguments() {
+            return Err(ExpressionError {
+                position: start,
+                kind: ErrorKind::WrongArguments(name.to_owned(), function.arguments()),
+            });
+        }
+        match function {
+            Function::Unary(function) => self.emit(Op::Unary(function), 1),
+            Function::Binary(function) => self.emit(Op::Binary(function), 2),
+        }
+    }
+
+    /// Adds `op`, which pops `pops` values and pushes one.
+    fn emit(&mut self, op: Op, pops: usize) -> Result<(), ExpressionError> {
+        self.depth = self.depth - pops + 1;
+        if self.depth > MAX_STACK {
+            return Err(ExpressionError {
+                position: self.position,
+                kind: ErrorKind::TooComplex,
+            });
+        }
+        self.ops.push(op);
+        Ok(())
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ExpressionError> {
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// The next character that isn't whitespace, without consuming it.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.source[self.position..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.position;
+        let rest = &self.source[start..];
+        self.position += rest.find(|c| !f(c)).unwrap_or(rest.len());
+        &self.source[start..self.position]
+    }
+
+    fn unexpected(&mut self) -> ExpressionError {
+        let kind = match self.peek() {
+            Some(c) => ErrorKind::UnexpectedCharacter(c),
+            None => ErrorKind::UnexpectedEnd,
+        };
+        ExpressionError {
+            position: self.position,
+            kind,
+        }
+    }
+}
+
+/// Why an expression didn't compile, and where.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpressionError {
+    /// Byte offset into the source.
+    pub position: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    BadNumber(String),
+    UnknownName(String),
+    UnknownFunction(String),
+    /// The function's name and how many arguments it takes.
+    WrongArguments(String, usize),
+    TooComplex,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let column = self.position + 1;
+        match &self.kind {
+            ErrorKind::UnexpectedCharacter(c) => {
+                write!(f, "Unexpected \"{}\" at column {}.", c, column)
+            }
+            ErrorKind::UnexpectedEnd => write!(f, "The expression ends too soon."),
+            ErrorKind::BadNumber(number) => {
+                write!(f, "\"{}\" at column {} isn't a number.", number, column)
+            }
+            ErrorKind::UnknownName(name) => write!(
+                f,
+                "There is no variable or constant named \"{}\" (column {}).",
+                name, column
+            ),
+            ErrorKind::UnknownFunction(name) => write!(
+                f,
+                "There is no function named \"{}\" (column {}).",
+                name, column
+            ),
+            ErrorKind::WrongArguments(name, count) => write!(
+                f,
+                "\"{}\" at column {} takes {} argument{}.",
+                name,
+                column,
+                count,
+                if *count == 1 { "" } else { "s" }
+            ),
+            ErrorKind::TooComplex => write!(f, "The expression is nested too deeply."),
+        }
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+/// A compiled program that can be handed to the engine and shared between copies of a node.
+/// Dropping the last reference on the audio thread defers freeing it to the collector.
+#[derive(Clone)]
+pub struct SharedProgram(Shared<Program>);
+
+impl SharedProgram {
+    pub fn new(collector: &Handle, program: Program) -> SharedProgram {
+        SharedProgram(Shared::new(collector, program))
+    }
+}
+
+impl Deref for SharedProgram {
+    type Target = Program;
+
+    fn deref(&self) -> &Program {
+        &self.0
+    }
+}
+
+impl PartialEq for SharedProgram {
+    fn eq(&self, other: &SharedProgram) -> bool {
+        std::ptr::eq::<Program>(&**self, &**other)
+    }
+}
+
+impl fmt::Debug for SharedProgram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(source: &str, variables: [f32; 4]) -> f32 {
+        Program::compile(source).unwrap().evaluate(&variables)
+    }
+
+    #[test]
+    fn precedence_and_grouping() {
+        assert_eq!(evaluate("1 + 2 * 3", [0.0; 4]), 7.0);
+        assert_eq!(evaluate("(1 + 2) * 3", [0.0; 4]), 9.0);
+        assert_eq!(evaluate("2 ^ 3 ^ 2", [0.0; 4]), 512.0);
+        assert_eq!(evaluate("-2 ^ 2", [0.0; 4]), -4.0);
+        assert_eq!(evaluate("10 - 4 - 3", [0.0; 4]), 3.0);
+        assert_eq!(evaluate("7 % 4 / 2", [0.0; 4]), 1.5);
+    }
+
+    #[test]
+    fn variables_constants_and_functions() {
+        let variables = [0.25, 2.0, 0.5, 3.0];
+        assert_eq!(evaluate("sin(a*2*pi) * b + c", variables), 2.5);
+        assert_eq!(evaluate("max(a, d) - min(b, c)", variables), 2.5);
+        assert_eq!(evaluate("pow(b, d)", variables), 8.0);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = |source| Program::compile(source).err().unwrap();
+        assert_eq!(error("a + x").kind, ErrorKind::UnknownName("x".to_owned()));
+        assert_eq!(error("a + x").position, 4);
+        assert_eq!(error("a +").kind, ErrorKind::UnexpectedEnd);
+        assert_eq!(error("a b").kind, ErrorKind::UnexpectedCharacter('b'));
+        assert_eq!(
+            error("sin(a, b)").kind,
+            ErrorKind::WrongArguments("sin".to_owned(), 1)
+        );
+        assert_eq!(
+            error("foo(a)").kind,
+            ErrorKind::UnknownFunction("foo".to_owned())
+        );
+        assert_eq!(
+            error("1.2.3").kind,
+            ErrorKind::BadNumber("1.2.3".to_owned())
+        );
+        let deep = format!("{}a{}", "(a + ".repeat(40), ")".repeat(40));
+        assert_eq!(error(&deep).kind, ErrorKind::TooComplex);
+        let parenthesised = format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert_eq!(error(&parenthesised).kind, ErrorKind::TooComplex);
+        let negated = format!("{}a", "-".repeat(100_000));
+        assert_eq!(error(&negated).kind, ErrorKind::TooComplex);
+        let powers = format!("a{}", " ^ a".repeat(100_000));
+        assert_eq!(error(&powers).kind, ErrorKind::TooComplex);
+    }
+}
@@ -1,41 +1,106 @@
+mod audio_file;
+pub mod dsp;
+mod expression;
 pub mod nodes;
 pub mod stack;
 
+use std::fmt;
+
+pub use audio_file::*;
 use basedrop::Owned;
+use enum_kinds::EnumKind;
+pub use expression::*;
 pub use nodes::*;
 pub use stack::*;
 use wmidi::MidiMessage;
 
 use super::bitset::BitSet;
 
+/// Control slots `midi_in` writes the keyboard to.
+pub const PITCH_SLOT: usize = 0;
+pub const GATE_SLOT: usize = 1;
+/// The latest note-on's velocity, from 0 to 1.
+pub const VELOCITY_SLOT: usize = 2;
+/// Counts note-ons, so envelopes can tell a new note from a held one while the gate is high.
+pub const TRIGGER_SLOT: usize = 3;
+/// The engine's tempo, in beats per minute, written before every block.
+pub const TEMPO_SLOT: usize = 4;
+
 pub struct Engine {
     pub channels: [Option<Owned<stack::Stack>>; 16],
     pitch: f64,
     notes: BitSet,
     notes_on: u8,
+    sample_rate: usize,
+    block_size: usize,
+    tempo: f32,
 }
 
 impl Engine {
-    pub fn new() -> Engine {
+    pub fn new(sample_rate: usize, block_size: usize) -> Engine {
         Engine {
             channels: [(); 16].map(|_| None),
             pitch: 110.0,
             notes: BitSet::new(),
             notes_on: 0,
+            sample_rate,
+            block_size,
+            tempo: Range::TEMPO.default,
+        }
+    }
+
+    /// Renders every channel, mixed, into `output_buffer`.
+    pub fn process(&mut self, output_buffer: &mut [f32]) {
+        output_buffer.fill(0.0);
+        for stack in self.channels.iter_mut().flatten() {
+            stack.data.control[TEMPO_SLOT] = self.tempo;
+            stack.process(output_buffer, self.sample_rate);
+        }
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// Anything timed in samples was computed for the old rate, so the channels start over.
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        self.sample_rate = sample_rate;
+        for stack in self.channels.iter_mut().flatten() {
+            stack.reset();
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Changes how many samples each channel processes at a time. The buffers are already
+    /// big enough, so this doesn't allocate; JACK calls it from the process thread.
+    pub fn set_block_size(&mut self, block_size: usize) {
+        self.block_size = block_size;
+        for stack in self.channels.iter_mut().flatten() {
+            stack.set_block_size(block_size);
         }
     }
 
+    /// Number of notes currently held.
+    pub fn voices(&self) -> u8 {
+        self.notes_on
+    }
+
     pub fn midi_in(&mut self, midi_message: MidiMessage) {
         let stack = match self.channels[0].as_mut() {
             Some(stack) => stack,
             None => return,
         };
         match midi_message {
-            MidiMessage::NoteOn(_channel, note, _velocity) => {
+            MidiMessage::NoteOn(_channel, note, velocity) => {
                 self.pitch = note.to_freq_f64();
                 self.notes_on += 1;
                 self.notes.set(note as u8);
-                stack.data.control[2] = 0.0; // Reset ADSR
+                let control = &mut stack.data.control;
+                control[VELOCITY_SLOT] = u8::from(velocity) as f32 / 127.0;
+                control[TRIGGER_SLOT] = (control[TRIGGER_SLOT] + 1.0) % 256.0;
             }
             MidiMessage::NoteOff(_channel, _note, _velocity) => {
                 self.notes_on = self.notes_on.saturating_sub(1);
@@ -48,50 +113,287 @@ impl Engine {
             _ => {}
         }
         if self.notes_on > 0 {
-            stack.data.control[1] = 1.0;
+            stack.data.control[GATE_SLOT] = 1.0;
         } else {
-            stack.data.control[1] = 0.0;
+            stack.data.control[GATE_SLOT] = 0.0;
         }
-        stack.data.control[0] = self.pitch as f32;
+        stack.data.control[PITCH_SLOT] = self.pitch as f32;
     }
 
-    pub fn run_command(&mut self, command: Command) {
+    pub fn run_command(&mut self, command: Command) -> Result<(), EngineError> {
         match command {
             Command::AddNode(index, node) => {
-                if let Some(Some(channel)) = self.channels.get_mut(index) {
-                    let _ = channel.nodes.try_push(node);
-                }
+                let stack = self.stack_mut(index)?;
+                stack
+                    .nodes
+                    .try_push(node)
+                    .map_err(|_| EngineError::NodeListFull(index))?;
             }
             Command::SetChannel(index, stack) => {
-                if let Some(channel) = self.channels.get_mut(index) {
-                    *channel = Some(stack);
-                }
+                let channel = self
+                    .channels
+                    .get_mut(index)
+                    .ok_or(EngineError::NoSuchChannel(index))?;
+                *channel = Some(stack);
             }
-            Command::ReplaceNodes(index, nodes) => {
-                if let Some(&mut Some(ref mut stack)) = self.channels.get_mut(index) {
-                    stack.nodes = nodes;
+            Command::RemoveNode(index, node) => {
+                let nodes = &mut self.stack_mut(index)?.nodes;
+                if node >= nodes.len() {
+                    return Err(EngineError::NoSuchNode(index, node));
                 }
+                // Dropping the node hands it to the collector rather than freeing it here.
+                nodes.remove(node);
             }
             Command::RemoveChannel(index) => {
-                if let Some(channel) = self.channels.get_mut(index) {
-                    *channel = None;
+                let channel = self
+                    .channels
+                    .get_mut(index)
+                    .ok_or(EngineError::NoSuchChannel(index))?;
+                *channel = None;
+            }
+            Command::SetPortValue {
+                channel,
+                node,
+                port,
+                value,
+            } => {
+                let input = self
+                    .stack_mut(channel)?
+                    .nodes
+                    .get_mut(node)
+                    .and_then(|node| node.inputs.get_mut(port))
+                    .ok_or(EngineError::NoSuchPort(channel, node, port))?;
+                if !input.range.contains(value) {
+                    return Err(EngineError::OutOfRange(channel, node, port));
                 }
+                input.value = value;
             }
             Command::ResetData => {
-                for channel in &mut self.channels {
-                    if let Some(stack) = channel {
-                        stack.data = StackData::default();
-                    }
+                for stack in self.channels.iter_mut().flatten() {
+                    stack.reset();
                 }
             }
+            Command::SetNodeAudio {
+                channel,
+                node,
+                audio,
+            } => {
+                self.stack_mut(channel)?
+                    .nodes
+                    .get_mut(node)
+                    .ok_or(EngineError::NoSuchNode(channel, node))?
+                    .set_audio(audio);
+            }
+            Command::SetNodeProgram {
+                channel,
+                node,
+                program,
+            } => {
+                self.stack_mut(channel)?
+                    .nodes
+                    .get_mut(node)
+                    .ok_or(EngineError::NoSuchNode(channel, node))?
+                    .set_program(program);
+            }
+            Command::SetTempo(tempo) => {
+                if !Range::TEMPO.contains(tempo) {
+                    return Err(EngineError::TempoOutOfRange);
+                }
+                self.tempo = tempo;
+            }
+        }
+        Ok(())
+    }
+
+    fn stack_mut(&mut self, index: usize) -> Result<&mut Stack, EngineError> {
+        match self.channels.get_mut(index) {
+            Some(Some(stack)) => Ok(stack),
+            _ => Err(EngineError::NoSuchChannel(index)),
         }
     }
 }
 
+#[derive(EnumKind)]
+#[enum_kind(CommandKind)]
 pub enum Command {
-    AddNode(usize, Node),
+    AddNode(usize, Owned<Node>),
     SetChannel(usize, Owned<stack::Stack>),
-    ReplaceNodes(usize, stack::NodeList),
+    /// Channel and node index. The nodes after it move up, keeping their state.
+    RemoveNode(usize, usize),
     RemoveChannel(usize),
+    /// Sets what an input reads while it isn't connected.
+    SetPortValue {
+        channel: usize,
+        node: usize,
+        port: usize,
+        value: f32,
+    },
     ResetData,
+    /// Hands a node audio loaded from a file.
+    SetNodeAudio {
+        channel: usize,
+        node: usize,
+        audio: SharedAudio,
+    },
+    /// Hands an expression node a compiled formula.
+    SetNodeProgram {
+        channel: usize,
+        node: usize,
+        program: SharedProgram,
+    },
+    /// Sets the clock tempo-synced nodes follow, in beats per minute.
+    SetTempo(f32),
+}
+
+impl Command {
+    /// Whether sending `self` makes `earlier` pointless, provided `earlier` hasn't been sent
+    /// yet.
+    pub fn supersedes(&self, earlier: &Command) -> bool {
+        use Command::*;
+        match (self, earlier) {
+            (ResetData, ResetData) | (SetTempo(_), SetTempo(_)) => true,
+            (
+                SetPortValue {
+                    channel,
+                    node,
+                    port,
+                    ..
+                },
+                SetPortValue {
+                    channel: earlier_channel,
+                    node: earlier_node,
+                    port: earlier_port,
+                    ..
+                },
+            ) => (channel, node, port) == (earlier_channel, earlier_node, earlier_port),
+            (
+                SetNodeAudio { channel, node, .. },
+                SetNodeAudio {
+                    channel: earlier_channel,
+                    node: earlier_node,
+                    ..
+                },
+            ) => (channel, node) == (earlier_channel, earlier_node),
+            (
+                SetNodeProgram { channel, node, .. },
+                SetNodeProgram {
+                    channel: earlier_channel,
+                    node: earlier_node,
+                    ..
+                },
+            ) => (channel, node) == (earlier_channel, earlier_node),
+            (
+                SetChannel(channel, _) | RemoveChannel(channel),
+                AddNode(earlier, _)
+                | SetChannel(earlier, _)
+                | RemoveNode(earlier, _)
+                | RemoveChannel(earlier)
+                | SetPortValue {
+                    channel: earlier, ..
+                }
+                | SetNodeAudio {
+                    channel: earlier, ..
+                }
+                | SetNodeProgram {
+                    channel: earlier, ..
+                },
+            ) => channel == earlier,
+            _ => false,
+        }
+    }
+}
+
+/// Why the engine rejected a command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EngineError {
+    NoSuchChannel(usize),
+    NodeListFull(usize),
+    /// Channel and node index.
+    NoSuchNode(usize, usize),
+    /// Channel, node and input index.
+    NoSuchPort(usize, usize, usize),
+    /// Channel, node and input index.
+    OutOfRange(usize, usize, usize),
+    TempoOutOfRange,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EngineError::NoSuchChannel(index) => write!(f, "Channel {} doesn't exist.", index),
+            EngineError::NodeListFull(index) => {
+                write!(f, "Channel {} can't hold any more nodes.", index)
+            }
+            EngineError::NoSuchNode(channel, node) => {
+                write!(f, "Channel {} has no node {}.", channel, node)
+            }
+            EngineError::NoSuchPort(channel, node, port) => write!(
+                f,
+                "Node {} on channel {} has no input {}.",
+                node, channel, port
+            ),
+            EngineError::OutOfRange(channel, node, port) => write!(
+                f,
+                "Value for input {} of node {} on channel {} is out of range.",
+                port, node, channel
+            ),
+            EngineError::TempoOutOfRange => write!(
+                f,
+                "Tempo must be between {} and {} BPM.",
+                Range::TEMPO.min,
+                Range::TEMPO.max
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    fn oscillator(slot: u8) -> Node {
+        let mut oscillator = Node::new(NodeKind::named("Oscillator").unwrap());
+        oscillator.outputs[0].slot = Some(Slot::Audio(slot));
+        oscillator
+    }
+
+    /// An engine playing `nodes` on channel 0.
+    fn engine(collector: &Collector, nodes: impl IntoIterator<Item = Node>) -> Engine {
+        let handle = collector.handle();
+        let nodes = nodes
+            .into_iter()
+            .map(|node| Owned::new(&handle, node))
+            .collect();
+        let stack = Stack::new(Owned::new(&handle, nodes), DEFAULT_BLOCK_SIZE);
+        let mut engine = Engine::new(SAMPLE_RATE, DEFAULT_BLOCK_SIZE);
+        engine
+            .run_command(Command::SetChannel(0, Owned::new(&handle, stack)))
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn removing_a_node_leaves_the_others_playing() {
+        let collector = Collector::new();
+        let mut reference = engine(&collector, [oscillator(0)]);
+        let mut expected = vec![0.0; 2 * DEFAULT_BLOCK_SIZE];
+        reference.process(&mut expected);
+        assert!(expected.iter().any(|&sample| sample != 0.0));
+
+        let mut engine = engine(&collector, [oscillator(1), oscillator(0)]);
+        let mut output = vec![0.0; 2 * DEFAULT_BLOCK_SIZE];
+        let (first, second) = output.split_at_mut(DEFAULT_BLOCK_SIZE);
+        engine.process(first);
+        engine.run_command(Command::RemoveNode(0, 0)).unwrap();
+        engine.process(second);
+        assert_eq!(output, expected);
+        assert_eq!(
+            engine.run_command(Command::RemoveNode(0, 1)),
+            Err(EngineError::NoSuchNode(0, 1))
+        );
+    }
 }
@@ -1,281 +1,387 @@
-use std::iter::FusedIterator;
-
-use arrayvec::ArrayVec;
-use enum_iterator::IntoEnumIterator;
-use enum_kinds::EnumKind;
+use std::{fmt, iter::FusedIterator};
 
 use super::*;
 
+mod adsr;
+mod delay;
+mod expression;
+mod filter;
+mod lfo;
+mod math;
+mod modulation;
+mod noise;
+mod operator;
+mod oscillator;
 mod port;
+mod reverb;
+mod sampler;
+mod shaper;
+mod wavetable;
+pub use operator::{Algorithm, ALGORITHMS};
 pub use port::*;
 
-#[derive(Copy, Clone, Debug, EnumKind, PartialEq)]
-#[enum_kind(NodeKind, derive(IntoEnumIterator))]
-pub enum Node {
-    Abs {
-        input: Port,
-        output: Port,
-    },
-    Add {
-        input_1: Port,
-        input_2: Port,
-        output: Port,
-    },
-    Adsr {
-        attack: Port,
-        decay: Port,
-        sustain: Port,
-        release: Port,
-        output: Port,
-        time: Port,
-        gate: Port,
-        previous_gate: f32,
-        voltage: f32,
-    },
-    Mul {
-        input_1: Port,
-        input_2: Port,
-        output: Port,
-    },
-    Oscillator {
-        frequency: Port,
-        phase: Port,
-        waveform: Port,
-        pulse_width: Port,
-        output: Port,
-    },
+/// The most outputs any node has.
+pub const MAX_OUTPUTS: usize = 8;
+
+const TIME: Range = Range::new(0.0, 10.0, 0.0, Unit::Seconds, Curve::Exponential);
+const LEVEL: Range = Range::new(0.0, 1.0, 0.0, Unit::Ratio, Curve::Linear);
+/// 0 keeps a node's own timing, 1 follows `beats` of `tempo` instead, which is connected to the
+/// engine's clock to begin with.
+const SYNC: Range = Range::new(0.0, 1.0, 0.0, Unit::None, Curve::Linear);
+const BEATS: Range = Range::new(0.125, 16.0, 1.0, Unit::None, Curve::Logarithmic);
+
+/// Every kind of node that can be added to a stack, in the order they're listed.
+static REGISTRY: [NodeDescriptor; 30] = [
+    NodeDescriptor::new::<math::Abs>("Abs"),
+    NodeDescriptor::new::<math::Add>("Add"),
+    NodeDescriptor::new::<adsr::Adsr>("ADSR"),
+    NodeDescriptor::new::<shaper::Bitcrusher>("Bitcrusher"),
+    NodeDescriptor::new::<modulation::Chorus>("Chorus"),
+    NodeDescriptor::new::<math::Clamp>("Clamp"),
+    NodeDescriptor::new::<shaper::Clip>("Clip"),
+    NodeDescriptor::new::<math::Comparator>("Comparator"),
+    NodeDescriptor::new::<math::Crossfade>("Crossfade"),
+    NodeDescriptor::new::<delay::Delay>("Delay"),
+    NodeDescriptor::new::<expression::Expression>("Expression"),
+    NodeDescriptor::new::<filter::StateVariable>("Filter"),
+    NodeDescriptor::new::<modulation::Flanger>("Flanger"),
+    NodeDescriptor::new::<math::Invert>("Invert"),
+    NodeDescriptor::new::<filter::Ladder>("Ladder"),
+    NodeDescriptor::new::<lfo::Lfo>("LFO"),
+    NodeDescriptor::new::<math::MinMax>("Min/Max"),
+    NodeDescriptor::new::<math::Mixer<2>>("Mixer 2"),
+    NodeDescriptor::new::<math::Mixer<4>>("Mixer 4"),
+    NodeDescriptor::new::<math::Mixer<8>>("Mixer 8"),
+    NodeDescriptor::new::<math::Mul>("Mul"),
+    NodeDescriptor::new::<noise::Noise>("Noise"),
+    NodeDescriptor::new::<operator::Operator>("Operator"),
+    NodeDescriptor::new::<oscillator::Oscillator>("Oscillator"),
+    NodeDescriptor::new::<modulation::Phaser>("Phaser"),
+    NodeDescriptor::new::<reverb::Reverb>("Reverb"),
+    NodeDescriptor::new::<sampler::Sampler>("Sampler"),
+    NodeDescriptor::new::<math::Scale>("Scale"),
+    NodeDescriptor::new::<shaper::Waveshaper>("Waveshaper"),
+    NodeDescriptor::new::<wavetable::WavetableOscillator>("Wavetable"),
+];
+
+/// What a kind of node does. `process` is called on the audio thread, so it mustn't
+/// allocate, lock or block; anything it needs has to be set up when the processor is built.
+pub trait NodeProcessor: Send {
+    /// Inputs, in the order `process` expects them.
+    fn inputs(&self) -> Ports;
+
+    /// Outputs, in the order `process` writes them.
+    fn outputs(&self) -> Ports;
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo);
+
+    /// Forgets anything left over from earlier blocks.
+    fn reset(&mut self) {}
+
+    /// Internal state, enough for `restore` to carry on where this processor is. Processors
+    /// that are mostly buffers, like delays, leave it out, so a copy of one starts out silent.
+    fn save(&self) -> Vec<f32> {
+        Vec::new()
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        let _ = state;
+    }
+
+    /// Whether this processor plays audio from a file, so the UI knows to offer loading one.
+    fn uses_audio(&self) -> bool {
+        false
+    }
+
+    fn set_audio(&mut self, audio: SharedAudio) {
+        let _ = audio;
+    }
+
+    /// Whether this processor runs a formula, so the UI knows to offer editing one.
+    fn uses_program(&self) -> bool {
+        false
+    }
+
+    fn set_program(&mut self, program: SharedProgram) {
+        let _ = program;
+    }
+
+    /// Does any work on a freshly loaded file that can't be done on the audio thread. This
+    /// runs on the thread that loaded it, before it's shared.
+    fn prepare_audio(file: &mut AudioFile)
+    where
+        Self: Sized,
+    {
+        let _ = file;
+    }
+}
+
+/// A node in a stack: its ports, plus the processor that reads and writes them.
+pub struct Node {
+    kind: NodeKind,
+    pub inputs: Ports,
+    pub outputs: Ports,
+    processor: Box<dyn NodeProcessor>,
+    /// Kept here as well as in the processor, so copies of the node get it too.
+    audio: Option<SharedAudio>,
+    /// Likewise.
+    program: Option<SharedProgram>,
 }
 
 impl Node {
-    pub fn new(variant: NodeKind) -> Node {
-        match variant {
-            NodeKind::Abs => Node::Abs {
-                input: Port::audio("input"),
-                output: Port::audio("output"),
-            },
-            NodeKind::Add => Node::Add {
-                input_1: Port::audio("input 1"),
-                input_2: Port::audio("input 2"),
-                output: Port::audio("output"),
-            },
-            NodeKind::Adsr => Node::Adsr {
-                attack: Port::audio("attack"),
-                decay: Port::audio("decay"),
-                sustain: Port::audio("sustain"),
-                release: Port::audio("release"),
-                gate: Port::audio("gate"),
-                time: Port::audio("time"),
-                output: Port::audio("output"),
-                previous_gate: 0.0,
-                voltage: 0.0,
-            },
-            NodeKind::Mul => Node::Mul {
-                input_1: Port::default(),
-                input_2: Port::default(),
-                output: Port::audio("output"),
-            },
-            NodeKind::Oscillator => Node::Oscillator {
-                frequency: Port::audio("frequency"),
-                phase: Port::new("phase", PortKind::Control(0.0)),
-                waveform: Port::new("waveform", PortKind::Control(0.0)),
-                pulse_width: Port::audio("pulse width"),
-                output: Port::audio("output"),
-            },
+    pub fn new(kind: NodeKind) -> Node {
+        let processor = (kind.0.build)();
+        Node {
+            kind,
+            inputs: processor.inputs(),
+            outputs: processor.outputs(),
+            processor,
+            audio: None,
+            program: None,
         }
     }
 
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    /// Processes the first `samples` samples of the stack's buffers.
     pub fn process(&mut self, samples: usize, data: &mut StackData, sample_rate: usize) {
-        assert!(0 < samples && samples <= 256);
-        use Node::*;
-        for input in self.inputs() {
-            input.read(data);
+        let mut io = NodeIo::new(data, samples, sample_rate);
+        self.processor.process(&mut self.inputs, &mut io);
+        data.commit(self.outputs.iter(), samples);
+    }
+
+    pub fn reset(&mut self) {
+        self.processor.reset();
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.kind.name()
+    }
+
+    pub fn uses_audio(&self) -> bool {
+        self.processor.uses_audio()
+    }
+
+    pub fn audio(&self) -> Option<&SharedAudio> {
+        self.audio.as_ref()
+    }
+
+    pub fn set_audio(&mut self, audio: SharedAudio) {
+        self.processor.set_audio(audio.clone());
+        self.audio = Some(audio);
+    }
+
+    pub fn uses_program(&self) -> bool {
+        self.processor.uses_program()
+    }
+
+    pub fn program(&self) -> Option<&SharedProgram> {
+        self.program.as_ref()
+    }
+
+    pub fn set_program(&mut self, program: SharedProgram) {
+        self.processor.set_program(program.clone());
+        self.program = Some(program);
+    }
+}
+
+impl Clone for Node {
+    fn clone(&self) -> Node {
+        let mut processor = (self.kind.0.build)();
+        processor.restore(&self.processor.save());
+        if let Some(audio) = &self.audio {
+            processor.set_audio(audio.clone());
         }
-        match self {
-            Add {
-                input_1,
-                input_2,
-                output,
-            } => {
-                for i in 0..samples {
-                    output[i] = input_1[i] + input_2[i];
-                }
-            }
-            Abs { input, output } => {
-                for i in 0..samples {
-                    output[i] = input[i].abs();
-                }
-            }
-            Adsr {
-                attack,
-                decay,
-                sustain,
-                release,
-                output,
-                time,
-                gate,
-                previous_gate,
-                voltage,
-            } => {
-                for i in 0..samples {
-                    let attack = attack[i];
-                    let decay = decay[i];
-                    let sustain = sustain[i];
-                    let release = release[i];
-                    if gate[i] != 0.0 {
-                        if *previous_gate == 0.0 {
-                            time[i] = 0.0;
-                        }
-                        if time[i] < attack {
-                            *voltage = time[i] / attack;
-                        } else if time[i] < attack + decay {
-                            *voltage =
-                                (decay + attack - time[i]) * (1.0 - sustain) / decay + sustain;
-                        }
-                    } else {
-                        *voltage -= sustain / (release * sample_rate as f32);
-                        *voltage = (*voltage).max(0.0);
-                    }
-                    output[i] = *voltage;
-                    *previous_gate = gate[i];
-                    time[i] += 1.0 / sample_rate as f32;
-                }
-            }
-            Mul {
-                input_1,
-                input_2,
-                output,
-            } => {
-                for i in 0..samples {
-                    output[i] = input_1[i] * input_2[i];
-                }
-            }
-            Oscillator {
-                frequency,
-                phase,
-                waveform,
-                pulse_width,
-                output,
-            } => {
-                for i in 0..samples {
-                    let waveform = waveform[i].clamp(0.0, 1.0).floor() as u8;
-                    phase[i] %= 1.0;
-                    output[i] = match waveform {
-                        0 => phase[i] * 2.0 - 1.0, // Sawtooth
-                        1 => {
-                            // PWM
-                            if phase[i] > pulse_width[i] {
-                                1.0
-                            } else {
-                                -1.0
-                            }
-                        }
-                        _ => 0.0,
-                    };
-                    phase[i] += frequency[i] / sample_rate as f32;
-                }
-            }
+        if let Some(program) = &self.program {
+            processor.set_program(program.clone());
         }
-        for output in self.outputs() {
-            output.write(data);
+        Node {
+            kind: self.kind,
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            processor,
+            audio: self.audio.clone(),
+            program: self.program.clone(),
         }
     }
+}
 
-    pub fn inputs(&mut self) -> impl Iterator<Item = &mut Port> {
-        let mut inputs: ArrayVec<&mut Port, 7> = ArrayVec::new();
-        match self {
-            Node::Abs { input, output: _ } => {
-                inputs.push(input);
-            }
-            Node::Add {
-                input_1,
-                input_2,
-                output: _,
-            } => {
-                inputs.push(input_1);
-                inputs.push(input_2);
-            }
-            Node::Adsr {
-                attack,
-                decay,
-                sustain,
-                release,
-                output,
-                time,
-                gate,
-                previous_gate: _,
-                voltage: _,
-            } => {
-                inputs.push(attack);
-                inputs.push(decay);
-                inputs.push(sustain);
-                inputs.push(release);
-                inputs.push(output);
-                inputs.push(time);
-                inputs.push(gate);
-            }
-            Node::Mul {
-                input_1,
-                input_2,
-                output: _,
-            } => {
-                inputs.push(input_1);
-                inputs.push(input_2);
-            }
-            Node::Oscillator {
-                frequency,
-                phase,
-                waveform,
-                pulse_width,
-                output: _,
-            } => {
-                inputs.push(frequency);
-                inputs.push(phase);
-                inputs.push(waveform);
-                inputs.push(pulse_width);
-            }
-        }
-        inputs.into_iter()
-    }
-
-    pub fn outputs(&mut self) -> impl Iterator<Item = &mut Port> {
-        let mut outputs: ArrayVec<&mut Port, 1> = ArrayVec::new();
-        match self {
-            Node::Abs { output, .. } => {
-                outputs.push(output);
-            }
-            Node::Add { output, .. } => {
-                outputs.push(output);
-            }
-            Node::Adsr { output, .. } => {
-                outputs.push(output);
-            }
-            Node::Mul { output, .. } => {
-                outputs.push(output);
-            }
-            Node::Oscillator { output, .. } => {
-                outputs.push(output);
-            }
-        }
-        outputs.into_iter()
+/// Nodes are equal when they're the same kind with the same ports, audio and program,
+/// whatever state their processors are in.
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        self.kind == other.kind
+            && self.inputs == other.inputs
+            && self.outputs == other.outputs
+            && self.audio == other.audio
+            && self.program == other.program
     }
+}
 
-    pub fn name(&self) -> &'static str {
-        NodeKind::from(self).name()
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Node")
+            .field("kind", &self.kind)
+            .field("inputs", &self.inputs)
+            .field("outputs", &self.outputs)
+            .field("audio", &self.audio)
+            .field("program", &self.program)
+            .finish()
+    }
+}
+
+/// An entry in the registry.
+pub struct NodeDescriptor {
+    pub name: &'static str,
+    build: fn() -> Box<dyn NodeProcessor>,
+    prepare_audio: fn(&mut AudioFile),
+}
+
+impl NodeDescriptor {
+    const fn new<P: NodeProcessor + Default + 'static>(name: &'static str) -> NodeDescriptor {
+        NodeDescriptor {
+            name,
+            build: build::<P>,
+            prepare_audio: P::prepare_audio,
+        }
     }
 }
 
+fn build<P: NodeProcessor + Default + 'static>() -> Box<dyn NodeProcessor> {
+    Box::new(P::default())
+}
+
+#[derive(Clone, Copy)]
+pub struct NodeKind(&'static NodeDescriptor);
+
 impl NodeKind {
-    pub fn iter() -> impl Iterator<Item = Self> + ExactSizeIterator + FusedIterator + Copy {
-        Self::into_enum_iter()
+    pub fn iter() -> impl ExactSizeIterator<Item = Self> + FusedIterator + Clone {
+        REGISTRY.iter().map(NodeKind)
     }
 
     pub fn name(&self) -> &'static str {
-        match self {
-            NodeKind::Abs => "Abs",
-            NodeKind::Add => "Add",
-            NodeKind::Adsr => "ADSR",
-            NodeKind::Mul => "Mul",
-            NodeKind::Oscillator => "Oscillator",
+        self.0.name
+    }
+
+    pub fn named(name: &str) -> Option<NodeKind> {
+        NodeKind::iter().find(|kind| kind.name() == name)
+    }
+
+    /// Gets a loaded file ready for this kind of node. Not for the audio thread.
+    pub fn prepare_audio(&self, file: &mut AudioFile) {
+        (self.0.prepare_audio)(file)
+    }
+}
+
+impl PartialEq for NodeKind {
+    fn eq(&self, other: &NodeKind) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl fmt::Debug for NodeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Runs `processor` on its own, with `inputs` unconnected, and returns what it wrote to each
+/// output.
+#[cfg(test)]
+fn render_processor(
+    processor: &mut dyn NodeProcessor,
+    inputs: &mut Ports,
+    samples: usize,
+    sample_rate: usize,
+) -> Vec<Vec<f32>> {
+    render(processor, inputs, &[], samples, sample_rate)
+}
+
+/// Like `render_processor`, but with `signal` connected to the first input.
+#[cfg(test)]
+fn process_signal(
+    processor: &mut dyn NodeProcessor,
+    inputs: &mut Ports,
+    signal: &[f32],
+    sample_rate: usize,
+) -> Vec<Vec<f32>> {
+    process_signals(processor, inputs, &[signal], sample_rate)
+}
+
+/// Like `process_signal`, but with a signal for each of the first few inputs. They should be
+/// the same length.
+#[cfg(test)]
+fn process_signals(
+    processor: &mut dyn NodeProcessor,
+    inputs: &mut Ports,
+    signals: &[&[f32]],
+    sample_rate: usize,
+) -> Vec<Vec<f32>> {
+    for (slot, input) in (1..).zip(inputs.iter_mut().take(signals.len())) {
+        input.slot = Some(Slot::Audio(slot));
+    }
+    render(processor, inputs, signals, signals[0].len(), sample_rate)
+}
+
+#[cfg(test)]
+fn render(
+    processor: &mut dyn NodeProcessor,
+    inputs: &mut Ports,
+    signals: &[&[f32]],
+    samples: usize,
+    sample_rate: usize,
+) -> Vec<Vec<f32>> {
+    let mut data = StackData::new(DEFAULT_BLOCK_SIZE);
+    let mut outputs = vec![Vec::with_capacity(samples); processor.outputs().len()];
+    let mut done = 0;
+    while done < samples {
+        let block = (samples - done).min(DEFAULT_BLOCK_SIZE);
+        for (slot, signal) in data.audio[1..].iter_mut().zip(signals) {
+            slot[..block].copy_from_slice(&signal[done..done + block]);
+        }
+        processor.process(inputs, &mut NodeIo::new(&mut data, block, sample_rate));
+        for (output, scratch) in outputs.iter_mut().zip(&data.scratch) {
+            output.extend_from_slice(&scratch[..block]);
+        }
+        done += block;
+    }
+    outputs
+}
+
+/// Energy in `signal` that isn't at a harmonic of `fundamental_bin`, relative to the total,
+/// in dB. The signal should hold a whole number of cycles.
+#[cfg(test)]
+fn aliasing(signal: &[f32], fundamental_bin: usize) -> f64 {
+    let length = signal.len();
+    let (mut harmonic, mut total) = (0.0, 0.0);
+    for bin in 0..=length / 2 {
+        let (mut re, mut im) = (0.0f64, 0.0f64);
+        for (n, &sample) in signal.iter().enumerate() {
+            let angle = std::f64::consts::TAU * ((bin * n) % length) as f64 / length as f64;
+            re += sample as f64 * angle.cos();
+            im -= sample as f64 * angle.sin();
+        }
+        let power = re * re + im * im;
+        total += power;
+        if bin % fundamental_bin == 0 {
+            harmonic += power;
+        }
+    }
+    10.0 * ((total - harmonic) / total).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_node_fits_its_ports() {
+        for kind in NodeKind::iter() {
+            let node = Node::new(kind);
+            assert!(node.inputs.len() <= MAX_PORTS, "{}", kind.name());
+            assert!(node.outputs.len() <= MAX_OUTPUTS, "{}", kind.name());
         }
     }
 }
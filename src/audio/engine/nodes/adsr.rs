@@ -0,0 +1,333 @@
+use super::*;
+
+/// How sharply exponential segments bend. Larger values spend more of the segment close to
+/// where it's heading, like a capacitor charging.
+const BEND: f32 = 5.0;
+
+/// 0 for straight segments, 1 for exponential ones.
+const SHAPE: Range = Range::new(0.0, 1.0, 0.0, Unit::None, Curve::Linear);
+
+/// 0 for legato, where a new note under a held gate carries on, 1 to start the attack again.
+const RETRIGGER: Range = Range::new(0.0, 1.0, 1.0, Unit::None, Curve::Linear);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum Stage {
+    #[default]
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Idle,
+        Stage::Attack,
+        Stage::Decay,
+        Stage::Sustain,
+        Stage::Release,
+    ];
+}
+
+/// Each segment takes exactly its time, however far it has to go: the attack always ends at
+/// the peak after `attack` seconds, and the release always reaches zero after `release`
+/// seconds. The gate starts the attack on a rising edge, and so does a change to `trigger`
+/// in retrigger mode, which is how a new note is told apart from a held one.
+#[derive(Default)]
+pub struct Adsr {
+    stage: Stage,
+    /// How far through the current segment, from 0 to 1.
+    progress: f32,
+    /// Where the current segment started.
+    from: f32,
+    level: f32,
+    /// The attack's target, scaled by velocity when the envelope was triggered.
+    peak: f32,
+    gate: bool,
+    trigger: f32,
+}
+
+/// Segment times in seconds, and the sustain level as a proportion of the peak.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Segments {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+    pub exponential: bool,
+}
+
+impl Adsr {
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.progress = 0.0;
+        self.from = self.level;
+    }
+
+    pub(super) fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Advances one sample. A rising gate, or a change to `trigger` with `retrigger` on,
+    /// starts the attack towards `peak`. Returns whether the release just finished.
+    pub(super) fn step(
+        &mut self,
+        gate_on: bool,
+        trigger: f32,
+        retrigger: bool,
+        peak: f32,
+        segments: Segments,
+        sample_rate: f32,
+    ) -> bool {
+        let retriggered = retrigger && trigger != self.trigger;
+        if gate_on && (!self.gate || retriggered) {
+            self.peak = peak;
+            self.enter(Stage::Attack);
+        } else if !gate_on && self.gate {
+            self.enter(Stage::Release);
+        }
+        self.gate = gate_on;
+        self.trigger = trigger;
+
+        let mut finished = false;
+        let sustain = segments.sustain * self.peak;
+        let (time, to) = match self.stage {
+            Stage::Idle => (0.0, 0.0),
+            Stage::Sustain => {
+                self.level = sustain;
+                (0.0, sustain)
+            }
+            Stage::Attack => (segments.attack, self.peak),
+            Stage::Decay => (segments.decay, sustain),
+            Stage::Release => (segments.release, 0.0),
+        };
+        if matches!(self.stage, Stage::Attack | Stage::Decay | Stage::Release) {
+            self.progress += 1.0 / (time.max(0.0) * sample_rate);
+            let progress = self.progress.min(1.0);
+            let curved = if segments.exponential {
+                (1.0 - (-BEND * progress).exp()) / (1.0 - (-BEND).exp())
+            } else {
+                progress
+            };
+            self.level = self.from + (to - self.from) * curved;
+            if self.progress >= 1.0 {
+                match self.stage {
+                    Stage::Attack => self.enter(Stage::Decay),
+                    Stage::Decay => self.enter(Stage::Sustain),
+                    _ => {
+                        self.enter(Stage::Idle);
+                        finished = true;
+                    }
+                }
+            }
+        }
+        finished
+    }
+}
+
+impl NodeProcessor for Adsr {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::new("attack", PortKind::Control).with_range(TIME.with_default(0.01)),
+            Port::new("decay", PortKind::Control).with_range(TIME.with_default(0.1)),
+            Port::new("sustain", PortKind::Control).with_range(LEVEL.with_default(0.7)),
+            Port::new("release", PortKind::Control).with_range(TIME.with_default(0.2)),
+            Port::audio("gate").with_range(Range::UNIPOLAR),
+            Port::new("shape", PortKind::Constant).with_range(SHAPE),
+            Port::new("retrigger", PortKind::Constant).with_range(RETRIGGER),
+            Port::new("trigger", PortKind::Constant).with_range(Range::UNIPOLAR),
+            Port::new("velocity", PortKind::Constant).with_range(LEVEL.with_default(1.0)),
+            Port::new("velocity amount", PortKind::Control).with_range(LEVEL),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output"), Port::audio("end of cycle")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [attack, decay, sustain, release, gate, shape, retrigger, trigger, velocity, amount] =
+            inputs.split();
+        let (attack, decay) = (io.input(attack), io.input(decay));
+        let (sustain, release) = (io.input(sustain), io.input(release));
+        let (gate, shape, retrigger) = (io.input(gate), io.input(shape), io.input(retrigger));
+        let (trigger, velocity, amount) = (io.input(trigger), io.input(velocity), io.input(amount));
+        let sample_rate = io.sample_rate();
+        let [output, end_of_cycle] = io.outputs();
+        for i in 0..output.len() {
+            let segments = Segments {
+                attack: attack.get(i),
+                decay: decay.get(i),
+                sustain: sustain.get(i),
+                release: release.get(i),
+                exponential: shape.get(i) >= 0.5,
+            };
+            let peak = 1.0 - amount.get(i) * (1.0 - velocity.get(i));
+            let finished = self.step(
+                gate.get(i) > 0.0,
+                trigger.get(i),
+                retrigger.get(i) >= 0.5,
+                peak,
+                segments,
+                sample_rate,
+            );
+            end_of_cycle[i] = finished as u8 as f32;
+            output[i] = self.level;
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Adsr::default();
+    }
+
+    fn save(&self) -> Vec<f32> {
+        let stage = Stage::ALL.iter().position(|&stage| stage == self.stage);
+        vec![
+            stage.unwrap_or_default() as f32,
+            self.progress,
+            self.from,
+            self.level,
+            self.peak,
+            self.gate as u8 as f32,
+            self.trigger,
+        ]
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let [stage, progress, from, level, peak, gate, trigger] = *state {
+            *self = Adsr {
+                stage: Stage::ALL.get(stage as usize).copied().unwrap_or_default(),
+                progress,
+                from,
+                level,
+                peak,
+                gate: gate != 0.0,
+                trigger,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 1000;
+
+    struct Envelope {
+        adsr: Adsr,
+        inputs: Ports,
+    }
+
+    impl Envelope {
+        /// Attack 0.1 s, decay 0.2 s, sustain 0.5 and release 0.3 s, at 1 kHz.
+        fn new() -> Envelope {
+            let adsr = Adsr::default();
+            let inputs = adsr.inputs();
+            let mut envelope = Envelope { adsr, inputs };
+            envelope
+                .set("attack", 0.1)
+                .set("decay", 0.2)
+                .set("sustain", 0.5)
+                .set("release", 0.3);
+            envelope
+        }
+
+        fn set(&mut self, name: &str, value: f32) -> &mut Envelope {
+            let port = self.inputs.named_mut(name).unwrap();
+            *port = port.with_value(value);
+            self
+        }
+
+        /// The output and end of cycle for the next `samples` samples.
+        fn run(&mut self, samples: usize) -> (Vec<f32>, Vec<f32>) {
+            let mut outputs =
+                render_processor(&mut self.adsr, &mut self.inputs, samples, SAMPLE_RATE);
+            let end_of_cycle = outputs.pop().unwrap();
+            (outputs.pop().unwrap(), end_of_cycle)
+        }
+    }
+
+    /// The first sample at which `output` has settled on `level`.
+    fn reaches(output: &[f32], level: f32) -> usize {
+        output
+            .iter()
+            .position(|&sample| (sample - level).abs() < 1e-4)
+            .unwrap()
+    }
+
+    fn assert_near(actual: usize, expected: usize) {
+        assert!(
+            actual.abs_diff(expected) <= 1,
+            "{} samples, expected {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn segments_take_their_time() {
+        for shape in [0.0, 1.0] {
+            let mut envelope = Envelope::new();
+            envelope.set("shape", shape);
+            let (held, _) = envelope.set("gate", 1.0).run(500);
+            assert_near(reaches(&held, 1.0), 99);
+            assert_near(reaches(&held[100..], 0.5), 199);
+            assert!(held[350..].iter().all(|&level| level == 0.5));
+
+            let (released, end_of_cycle) = envelope.set("gate", 0.0).run(500);
+            assert_near(reaches(&released, 0.0), 299);
+            assert_eq!(
+                end_of_cycle.iter().filter(|&&pulse| pulse == 1.0).count(),
+                1
+            );
+            assert_eq!(released[reaches(&end_of_cycle, 1.0)], 0.0);
+        }
+    }
+
+    #[test]
+    fn release_time_ignores_sustain() {
+        for sustain in [0.1, 0.9] {
+            let mut envelope = Envelope::new();
+            envelope.set("sustain", sustain).set("gate", 1.0).run(500);
+            let (released, _) = envelope.set("gate", 0.0).run(500);
+            assert_near(reaches(&released, 0.0), 299);
+        }
+    }
+
+    #[test]
+    fn release_during_attack_starts_from_the_current_level() {
+        let mut envelope = Envelope::new();
+        let (attack, _) = envelope.set("gate", 1.0).run(50);
+        let (released, _) = envelope.set("gate", 0.0).run(500);
+        assert!(released[0] < attack[49] && released[0] > 0.4);
+        assert_near(reaches(&released, 0.0), 299);
+    }
+
+    #[test]
+    fn retrigger_restarts_the_attack_and_legato_does_not() {
+        for retrigger in [0.0, 1.0] {
+            let mut envelope = Envelope::new();
+            envelope
+                .set("retrigger", retrigger)
+                .set("gate", 1.0)
+                .run(500);
+            let (held, _) = envelope.set("trigger", 1.0).run(500);
+            if retrigger == 1.0 {
+                assert_near(reaches(&held, 1.0), 99);
+            } else {
+                assert!(held.iter().all(|&level| level == 0.5));
+            }
+        }
+    }
+
+    #[test]
+    fn velocity_scales_the_envelope() {
+        let mut envelope = Envelope::new();
+        envelope.set("velocity", 0.5).set("velocity amount", 1.0);
+        let (held, _) = envelope.set("gate", 1.0).run(500);
+        assert_near(reaches(&held, 0.5), 99);
+        assert_eq!(held[499], 0.25);
+    }
+}
@@ -0,0 +1,152 @@
+use super::*;
+use crate::audio::engine::{dsp::DelayLine, TEMPO_SLOT};
+
+const MAX_TIME: f32 = 2.0;
+const TIME: Range = Range::new(0.0, MAX_TIME, 0.25, Unit::Seconds, Curve::Exponential);
+const FEEDBACK: Range = Range::new(0.0, 0.95, 0.3, Unit::Ratio, Curve::Linear);
+
+/// An echo. `time` is read every sample and interpolated, so modulating it bends the pitch
+/// of the echoes smoothly instead of clicking. Synced, the echoes come `beats` beats apart.
+pub struct Delay {
+    line: DelayLine,
+}
+
+impl Default for Delay {
+    fn default() -> Delay {
+        Delay {
+            line: DelayLine::with_seconds(MAX_TIME),
+        }
+    }
+}
+
+impl NodeProcessor for Delay {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("input"),
+            Port::audio("time").with_range(TIME),
+            Port::new("feedback", PortKind::Control).with_range(FEEDBACK),
+            Port::new("mix", PortKind::Control).with_range(LEVEL.with_default(0.5)),
+            Port::new("sync", PortKind::Constant).with_range(SYNC),
+            Port::new("tempo", PortKind::Control)
+                .with_range(Range::TEMPO)
+                .with_slot(Slot::Control(TEMPO_SLOT as u8)),
+            Port::new("beats", PortKind::Control).with_range(BEATS.with_default(0.5)),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, time, feedback, mix, sync, tempo, beats] = inputs.split();
+        let (input, time, feedback) = (io.input(input), io.input(time), io.input(feedback));
+        let (mix, sync) = (io.input(mix), io.input(sync));
+        let (tempo, beats) = (io.input(tempo), io.input(beats));
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let seconds = if sync.get(i) >= 0.5 {
+                beats.get(i) * 60.0 / tempo.get(i).max(Range::TEMPO.min)
+            } else {
+                time.get(i)
+            };
+            let x = input.get(i);
+            let delayed = self.line.read(seconds * sample_rate);
+            let feedback = feedback.get(i).clamp(FEEDBACK.min, FEEDBACK.max);
+            self.line.push(x + delayed * feedback);
+            let mix = mix.get(i);
+            *output = x * (1.0 - mix) + delayed * mix;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::{Collector, Owned};
+
+    use super::*;
+    use crate::audio::engine::{Command, Engine, Stack};
+
+    const SAMPLE_RATE: usize = 1000;
+
+    fn impulse(length: usize) -> Vec<f32> {
+        let mut signal = vec![0.0; length];
+        signal[0] = 1.0;
+        signal
+    }
+
+    /// The wet signal for an impulse, with `time` and `feedback` set.
+    fn echoes(time: f32, feedback: f32) -> Vec<f32> {
+        let mut delay = Delay::default();
+        let mut inputs = delay.inputs();
+        for (port, value) in [("time", time), ("feedback", feedback), ("mix", 1.0)] {
+            let port = inputs.named_mut(port).unwrap();
+            *port = port.with_value(value);
+        }
+        let signal = impulse(2 * SAMPLE_RATE);
+        process_signal(&mut delay, &mut inputs, &signal, SAMPLE_RATE).remove(0)
+    }
+
+    #[test]
+    fn echo_lands_at_the_set_time() {
+        let output = echoes(0.25, 0.0);
+        assert!(output[..250].iter().all(|&x| x == 0.0));
+        assert!((output[250] - 1.0).abs() < 1e-6, "{}", output[250]);
+        assert!(output[251..].iter().all(|&x| x.abs() < 1e-6));
+    }
+
+    #[test]
+    fn feedback_decays() {
+        let output = echoes(0.25, 0.5);
+        for echo in 1..8 {
+            let expected = 0.5f32.powi(echo - 1);
+            let got = output[250 * echo as usize];
+            assert!((got - expected).abs() < 1e-6, "echo {}: {}", echo, got);
+        }
+    }
+
+    #[test]
+    fn sync_follows_the_engine_tempo() {
+        let collector = Collector::new();
+        let handle = collector.handle();
+        let mut delay = Node::new(NodeKind::named("Delay").unwrap());
+        for (port, value) in [
+            ("feedback", 0.0),
+            ("mix", 1.0),
+            ("sync", 1.0),
+            ("beats", 1.0),
+        ] {
+            let port = delay.inputs.named_mut(port).unwrap();
+            *port = port.with_value(value);
+        }
+        delay.inputs[0].slot = Some(Slot::Audio(1));
+        delay.outputs[0].slot = Some(Slot::Audio(0));
+        let nodes = [Owned::new(&handle, delay)].into_iter().collect();
+        let stack = Stack::new(Owned::new(&handle, nodes), DEFAULT_BLOCK_SIZE);
+        let mut engine = Engine::new(SAMPLE_RATE, DEFAULT_BLOCK_SIZE);
+        engine
+            .run_command(Command::SetChannel(0, Owned::new(&handle, stack)))
+            .unwrap();
+        engine.run_command(Command::SetTempo(150.0)).unwrap();
+
+        let mut output = vec![0.0; 2 * SAMPLE_RATE];
+        let (first, rest) = output.split_at_mut(DEFAULT_BLOCK_SIZE);
+        let input = &mut engine.channels[0].as_mut().unwrap().data.audio[1];
+        input[0] = 1.0;
+        engine.process(first);
+        engine.channels[0].as_mut().unwrap().data.audio[1].fill(0.0);
+        engine.process(rest);
+        // One beat at 150 BPM is 0.4 s. The first block ramps from the default tempo, but the
+        // echo is read after that.
+        let peak = (0..output.len())
+            .max_by(|&a, &b| output[a].total_cmp(&output[b]))
+            .unwrap();
+        assert_eq!(peak, 400);
+        assert!((output[400] - 1.0).abs() < 1e-6, "{}", output[400]);
+    }
+}
@@ -0,0 +1,45 @@
+use super::*;
+use crate::audio::engine::{SharedProgram, VARIABLES};
+
+/// Evaluates a formula once per sample, with its variables read from the inputs of the same
+/// names. The formula is compiled on the UI thread; until there is one, the output is silent,
+/// and so is anything that isn't a finite number, like dividing by zero.
+#[derive(Default)]
+pub struct Expression {
+    program: Option<SharedProgram>,
+}
+
+impl NodeProcessor for Expression {
+    fn inputs(&self) -> Ports {
+        Ports::from(VARIABLES.map(Port::audio))
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let variables: [&mut Port; VARIABLES.len()] = inputs.split();
+        let variables = variables.map(|variable| io.input(variable));
+        let [output] = io.outputs();
+        let program = match &self.program {
+            Some(program) => program,
+            None => {
+                output.fill(0.0);
+                return;
+            }
+        };
+        for (i, output) in output.iter_mut().enumerate() {
+            let value = program.evaluate(&variables.map(|variable| variable.get(i)));
+            *output = if value.is_finite() { value } else { 0.0 };
+        }
+    }
+
+    fn uses_program(&self) -> bool {
+        true
+    }
+
+    fn set_program(&mut self, program: SharedProgram) {
+        self.program = Some(program);
+    }
+}
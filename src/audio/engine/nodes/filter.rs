@@ -0,0 +1,226 @@
+use super::*;
+use crate::audio::engine::dsp::{prewarp, OnePole};
+
+const CUTOFF: Range = Range::FREQUENCY.with_default(1000.0);
+const DRIVE: Range = Range::new(1.0, 10.0, 1.0, Unit::Ratio, Curve::Exponential);
+
+/// A 12 dB state-variable filter, with all four responses at once.
+#[derive(Default)]
+pub struct StateVariable {
+    integrators: [f32; 2],
+}
+
+impl NodeProcessor for StateVariable {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("input"),
+            Port::audio("cutoff").with_range(CUTOFF),
+            Port::audio("resonance").with_range(Range::UNIPOLAR),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("low pass"),
+            Port::audio("high pass"),
+            Port::audio("band pass"),
+            Port::audio("notch"),
+        ])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, cutoff, resonance] = inputs.split();
+        let (input, cutoff, resonance) = (io.input(input), io.input(cutoff), io.input(resonance));
+        let sample_rate = io.sample_rate();
+        let [low, high, band, notch] = io.outputs();
+        let [ic1, ic2] = &mut self.integrators;
+        for i in 0..low.len() {
+            let g = prewarp(cutoff.get(i), sample_rate);
+            // Damping; it would ring forever at 0.
+            let k = 2.0 - 2.0 * resonance.get(i).clamp(0.0, 0.99);
+            let a1 = 1.0 / (1.0 + g * (g + k));
+            let (a2, a3) = (g * a1, g * g * a1);
+            let x = input.get(i);
+            let v3 = x - *ic2;
+            let v1 = a1 * *ic1 + a2 * v3;
+            let v2 = *ic2 + a2 * *ic1 + a3 * v3;
+            *ic1 = 2.0 * v1 - *ic1;
+            *ic2 = 2.0 * v2 - *ic2;
+            low[i] = v2;
+            band[i] = v1;
+            high[i] = x - k * v1 - v2;
+            notch[i] = low[i] + high[i];
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = StateVariable::default();
+    }
+
+    fn save(&self) -> Vec<f32> {
+        self.integrators.to_vec()
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let Ok(integrators) = state.try_into() {
+            self.integrators = integrators;
+        }
+    }
+}
+
+/// A 24 dB low-pass made of four one-poles in a loop, which self-oscillates at full
+/// resonance. `drive` pushes the input into a tanh before the first stage.
+#[derive(Default)]
+pub struct Ladder {
+    stages: [OnePole; 4],
+}
+
+impl NodeProcessor for Ladder {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("input"),
+            Port::audio("cutoff").with_range(CUTOFF),
+            Port::audio("resonance").with_range(Range::UNIPOLAR),
+            Port::control("drive", 1.0).with_range(DRIVE),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, cutoff, resonance, drive] = inputs.split();
+        let (input, cutoff, resonance) = (io.input(input), io.input(cutoff), io.input(resonance));
+        let drive = io.input(drive);
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let feedthrough = OnePole::feedthrough(prewarp(cutoff.get(i), sample_rate));
+            let k = 4.0 * resonance.get(i).clamp(0.0, 1.0);
+            // Solve the feedback loop as if it were linear, then saturate what goes into it.
+            let residue = self.stages.iter().fold(0.0, |sum, stage| {
+                sum * feedthrough + stage.residue(feedthrough)
+            });
+            let driven = drive.get(i) * input.get(i);
+            let gain = feedthrough.powi(4);
+            let estimate = (gain * driven + residue) / (1.0 + k * gain);
+            let mut signal = (driven - k * estimate).tanh();
+            for stage in &mut self.stages {
+                signal = stage.low_pass(signal, feedthrough);
+            }
+            *output = signal;
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Ladder::default();
+    }
+
+    fn save(&self) -> Vec<f32> {
+        self.stages.iter().map(|stage| stage.state).collect()
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if state.len() == self.stages.len() {
+            for (stage, &state) in self.stages.iter_mut().zip(state) {
+                stage.state = state;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+    const LENGTH: usize = 9600;
+
+    fn sine(frequency: f32, amplitude: f32) -> Vec<f32> {
+        (0..LENGTH)
+            .map(|n| amplitude * (TAU * frequency * n as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    /// Of the second half, once the filter has settled.
+    fn rms(signal: &[f32]) -> f32 {
+        let settled = &signal[signal.len() / 2..];
+        (settled.iter().map(|x| x * x).sum::<f32>() / settled.len() as f32).sqrt()
+    }
+
+    /// A full-scale square through `processor`, with the cutoff swept from below 20 Hz to past
+    /// Nyquist at an audio rate, and resonance given by `resonance`.
+    fn sweep(processor: &mut dyn NodeProcessor, resonance: &[f32]) -> Vec<Vec<f32>> {
+        let square: Vec<f32> = sine(110.0, 1.0).iter().map(|x| x.signum()).collect();
+        let cutoff: Vec<f32> = sine(1500.0, 1.0)
+            .iter()
+            .map(|x| 10.0 * 3000.0f32.powf(0.5 + 0.5 * x))
+            .collect();
+        let mut inputs = processor.inputs();
+        process_signals(
+            processor,
+            &mut inputs,
+            &[&square, &cutoff, resonance],
+            SAMPLE_RATE,
+        )
+    }
+
+    fn assert_bounded(outputs: &[Vec<f32>], bound: f32) {
+        for output in outputs {
+            assert!(output.iter().all(|x| x.is_finite() && x.abs() < bound));
+        }
+    }
+
+    #[test]
+    fn modulation_keeps_the_output_bounded() {
+        let full = vec![1.0; LENGTH];
+        let swept: Vec<f32> = sine(700.0, 0.5).iter().map(|x| x + 0.5).collect();
+        for resonance in [&full, &swept] {
+            assert_bounded(&sweep(&mut StateVariable::default(), resonance), 100.0);
+            assert_bounded(&sweep(&mut Ladder::default(), resonance), 2.0);
+        }
+    }
+
+    #[test]
+    fn state_variable_responses() {
+        let response = |frequency| {
+            let mut filter = StateVariable::default();
+            let mut inputs = filter.inputs();
+            let input = sine(frequency, 1.0);
+            let outputs = process_signal(&mut filter, &mut inputs, &input, SAMPLE_RATE);
+            let gain = |output: &[f32]| rms(output) / rms(&input);
+            (gain(&outputs[0]), gain(&outputs[1]))
+        };
+        let (low, high) = response(100.0);
+        assert!(
+            low > 0.95 && high < 0.05,
+            "100 Hz: low {} high {}",
+            low,
+            high
+        );
+        let (low, high) = response(10000.0);
+        assert!(
+            low < 0.05 && high > 0.95,
+            "10 kHz: low {} high {}",
+            low,
+            high
+        );
+    }
+
+    #[test]
+    fn ladder_response() {
+        let gain = |frequency| {
+            let mut filter = Ladder::default();
+            let mut inputs = filter.inputs();
+            // Quiet enough for the saturation not to matter.
+            let input = sine(frequency, 0.01);
+            let output = process_signal(&mut filter, &mut inputs, &input, SAMPLE_RATE);
+            rms(&output[0]) / rms(&input)
+        };
+        assert!(gain(100.0) > 0.95, "100 Hz: {}", gain(100.0));
+        assert!(gain(10000.0) < 0.001, "10 kHz: {}", gain(10000.0));
+    }
+}
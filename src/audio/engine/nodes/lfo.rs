@@ -0,0 +1,167 @@
+use super::*;
+use crate::audio::engine::{dsp::Random, TEMPO_SLOT};
+
+const RATE: Range = Range::new(0.01, 50.0, 1.0, Unit::Hz, Curve::Logarithmic);
+const SHAPE: Range = Range::new(0.0, 4.0, 0.0, Unit::None, Curve::Linear);
+/// 0 swings from -1 to 1, 1 from 0 to 1.
+const UNIPOLAR: Range = Range::new(0.0, 1.0, 0.0, Unit::None, Curve::Linear);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Shape {
+    Sine,
+    Triangle,
+    Sawtooth,
+    Square,
+    SampleAndHold,
+}
+
+impl Shape {
+    fn from_value(value: f32) -> Shape {
+        match value.round() as i32 {
+            i32::MIN..=0 => Shape::Sine,
+            1 => Shape::Triangle,
+            2 => Shape::Sawtooth,
+            3 => Shape::Square,
+            _ => Shape::SampleAndHold,
+        }
+    }
+}
+
+/// A low-frequency oscillator. Synced, one cycle lasts `beats` beats of `tempo`, which is
+/// connected to the engine's clock to begin with. A rising edge at `gate` starts the cycle
+/// over.
+#[derive(Default)]
+pub struct Lfo {
+    phase: f32,
+    gate: bool,
+    /// The sample-and-hold level, picked at the start of each cycle.
+    held: f32,
+    random: Random,
+}
+
+impl NodeProcessor for Lfo {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::new("rate", PortKind::Control).with_range(RATE),
+            Port::new("shape", PortKind::Constant).with_range(SHAPE),
+            Port::new("unipolar", PortKind::Constant).with_range(UNIPOLAR),
+            Port::new("phase", PortKind::Control).with_range(Range::UNIPOLAR),
+            Port::audio("gate").with_range(Range::UNIPOLAR),
+            Port::new("sync", PortKind::Constant).with_range(SYNC),
+            Port::new("tempo", PortKind::Control)
+                .with_range(Range::TEMPO)
+                .with_slot(Slot::Control(TEMPO_SLOT as u8)),
+            Port::new("beats", PortKind::Control).with_range(BEATS),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [rate, shape, unipolar, phase, gate, sync, tempo, beats] = inputs.split();
+        let (rate, shape, unipolar) = (io.input(rate), io.input(shape), io.input(unipolar));
+        let (phase, gate, sync) = (io.input(phase), io.input(gate), io.input(sync));
+        let (tempo, beats) = (io.input(tempo), io.input(beats));
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let gate_on = gate.get(i) > 0.0;
+            if gate_on && !self.gate {
+                self.phase = 0.0;
+                self.held = self.random.next_bipolar();
+            }
+            self.gate = gate_on;
+
+            let frequency = if sync.get(i) >= 0.5 {
+                tempo.get(i) / 60.0 / beats.get(i).max(BEATS.min)
+            } else {
+                rate.get(i)
+            };
+            let read = (self.phase + phase.get(i)).rem_euclid(1.0);
+            let value = match Shape::from_value(shape.get(i)) {
+                Shape::Sine => (read * std::f32::consts::TAU).sin(),
+                Shape::Triangle => 1.0 - 4.0 * ((read + 0.25).fract() - 0.5).abs(),
+                Shape::Sawtooth => read * 2.0 - 1.0,
+                Shape::Square if read < 0.5 => 1.0,
+                Shape::Square => -1.0,
+                Shape::SampleAndHold => self.held,
+            };
+            *output = if unipolar.get(i) >= 0.5 {
+                (value + 1.0) * 0.5
+            } else {
+                value
+            };
+
+            self.phase += (frequency / sample_rate).clamp(0.0, 0.5);
+            if self.phase >= 1.0 {
+                self.phase -= 1.0;
+                self.held = self.random.next_bipolar();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Lfo::default();
+    }
+
+    fn save(&self) -> Vec<f32> {
+        let [high, low] = self.random.save();
+        vec![self.phase, self.gate as u8 as f32, self.held, high, low]
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let [phase, gate, held, high, low] = *state {
+            self.phase = phase;
+            self.gate = gate != 0.0;
+            self.held = held;
+            self.random.restore([high, low]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::{Collector, Owned};
+
+    use super::*;
+    use crate::audio::engine::{Command, Engine, Stack};
+
+    const SAMPLE_RATE: usize = 1000;
+
+    #[test]
+    fn sync_follows_the_engine_tempo() {
+        let collector = Collector::new();
+        let handle = collector.handle();
+        let mut lfo = Node::new(NodeKind::named("LFO").unwrap());
+        for (port, value) in [("shape", 2.0), ("sync", 1.0), ("beats", 1.0)] {
+            let port = lfo.inputs.named_mut(port).unwrap();
+            *port = port.with_value(value);
+        }
+        lfo.outputs[0].slot = Some(Slot::Audio(0));
+        let nodes = [Owned::new(&handle, lfo)].into_iter().collect();
+        let stack = Stack::new(Owned::new(&handle, nodes), DEFAULT_BLOCK_SIZE);
+        let mut engine = Engine::new(SAMPLE_RATE, DEFAULT_BLOCK_SIZE);
+        engine
+            .run_command(Command::SetChannel(0, Owned::new(&handle, stack)))
+            .unwrap();
+        engine.run_command(Command::SetTempo(150.0)).unwrap();
+
+        let mut output = vec![0.0; 4 * SAMPLE_RATE];
+        engine.process(&mut output);
+        // The sawtooth wraps once a cycle. The first block ramps from the default tempo.
+        let wraps: Vec<usize> = (DEFAULT_BLOCK_SIZE..output.len())
+            .filter(|&i| output[i] < output[i - 1] - 1.0)
+            .collect();
+        assert!(wraps.len() >= 8);
+        for pair in wraps.windows(2) {
+            // One beat at 150 BPM is 0.4 s.
+            assert!(
+                pair[1] - pair[0] == 400 || pair[1] - pair[0] == 401,
+                "{:?}",
+                wraps
+            );
+        }
+    }
+}
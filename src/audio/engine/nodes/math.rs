@@ -0,0 +1,412 @@
+use std::f32::consts::FRAC_PI_2;
+
+use arrayvec::ArrayVec;
+
+use super::*;
+
+const GAIN: Range = Range::new(0.0, 2.0, 1.0, Unit::Ratio, Curve::Linear);
+const SCALE: Range = Range::new(-2.0, 2.0, 1.0, Unit::Ratio, Curve::Linear);
+const HYSTERESIS: Range = Range::new(0.0, 1.0, 0.0, Unit::None, Curve::Linear);
+/// 0 for a linear crossfade, 1 for equal power, which doesn't dip in the middle when the
+/// inputs are unrelated.
+const EQUAL_POWER: Range = Range::new(0.0, 1.0, 0.0, Unit::None, Curve::Linear);
+
+#[derive(Default)]
+pub struct Abs;
+
+impl NodeProcessor for Abs {
+    fn inputs(&self) -> Ports {
+        Ports::from([Port::audio("input")])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input] = inputs.split();
+        let input = io.input(input);
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            *output = input.get(i).abs();
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Add;
+
+impl NodeProcessor for Add {
+    fn inputs(&self) -> Ports {
+        Ports::from([Port::audio("input 1"), Port::audio("input 2")])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input_1, input_2] = inputs.split();
+        let (input_1, input_2) = (io.input(input_1), io.input(input_2));
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            *output = input_1.get(i) + input_2.get(i);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Mul;
+
+impl NodeProcessor for Mul {
+    fn inputs(&self) -> Ports {
+        Ports::from([Port::audio("input 1"), Port::audio("input 2")])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input_1, input_2] = inputs.split();
+        let (input_1, input_2) = (io.input(input_1), io.input(input_2));
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            *output = input_1.get(i) * input_2.get(i);
+        }
+    }
+}
+
+const MAX_MIXER_INPUTS: usize = 8;
+const MIXER_INPUTS: [&str; MAX_MIXER_INPUTS] = [
+    "input 1", "input 2", "input 3", "input 4", "input 5", "input 6", "input 7", "input 8",
+];
+const MIXER_GAINS: [&str; MAX_MIXER_INPUTS] = [
+    "gain 1", "gain 2", "gain 3", "gain 4", "gain 5", "gain 6", "gain 7", "gain 8",
+];
+
+/// Sums `N` inputs, each with its own gain. The inputs come first, then their gains.
+#[derive(Default)]
+pub struct Mixer<const N: usize>;
+
+impl<const N: usize> NodeProcessor for Mixer<N> {
+    fn inputs(&self) -> Ports {
+        let inputs = MIXER_INPUTS[..N].iter().map(|&name| Port::audio(name));
+        let gains = MIXER_GAINS[..N]
+            .iter()
+            .map(|&name| Port::new(name, PortKind::Control).with_range(GAIN));
+        inputs.chain(gains).collect()
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let (inputs, gains) = inputs.split_at_mut(N);
+        let inputs: ArrayVec<Input, MAX_MIXER_INPUTS> =
+            inputs.iter_mut().map(|input| io.input(input)).collect();
+        let gains: ArrayVec<Input, MAX_MIXER_INPUTS> =
+            gains.iter_mut().map(|gain| io.input(gain)).collect();
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            *output = inputs
+                .iter()
+                .zip(&gains)
+                .map(|(input, gain)| input.get(i) * gain.get(i))
+                .sum();
+        }
+    }
+}
+
+/// Multiplies by `scale`, then adds `offset`. A negative scale inverts.
+#[derive(Default)]
+pub struct Scale;
+
+impl NodeProcessor for Scale {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("input"),
+            Port::new("scale", PortKind::Control).with_range(SCALE),
+            Port::new("offset", PortKind::Control).with_range(Range::BIPOLAR),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, scale, offset] = inputs.split();
+        let (input, scale, offset) = (io.input(input), io.input(scale), io.input(offset));
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            *output = input.get(i) * scale.get(i) + offset.get(i);
+        }
+    }
+}
+
+/// Fades from `a` at a `mix` of 0 to `b` at 1.
+#[derive(Default)]
+pub struct Crossfade;
+
+impl NodeProcessor for Crossfade {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("a"),
+            Port::audio("b"),
+            Port::audio("mix").with_range(Range::UNIPOLAR.with_default(0.5)),
+            Port::new("equal power", PortKind::Constant).with_range(EQUAL_POWER),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [a, b, mix, equal_power] = inputs.split();
+        let (a, b, mix) = (io.input(a), io.input(b), io.input(mix));
+        let equal_power = io.input(equal_power);
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let mix = mix.get(i).clamp(0.0, 1.0);
+            let (gain_a, gain_b) = if equal_power.get(i) >= 0.5 {
+                let (sin, cos) = (mix * FRAC_PI_2).sin_cos();
+                (cos, sin)
+            } else {
+                (1.0 - mix, mix)
+            };
+            *output = a.get(i) * gain_a + b.get(i) * gain_b;
+        }
+    }
+}
+
+/// The lower and the higher of two inputs.
+#[derive(Default)]
+pub struct MinMax;
+
+impl NodeProcessor for MinMax {
+    fn inputs(&self) -> Ports {
+        Ports::from([Port::audio("input 1"), Port::audio("input 2")])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("min"), Port::audio("max")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input_1, input_2] = inputs.split();
+        let (input_1, input_2) = (io.input(input_1), io.input(input_2));
+        let [min, max] = io.outputs();
+        for i in 0..min.len() {
+            let (input_1, input_2) = (input_1.get(i), input_2.get(i));
+            min[i] = input_1.min(input_2);
+            max[i] = input_1.max(input_2);
+        }
+    }
+}
+
+/// A gate that opens while `input` is above `threshold`. With hysteresis, it opens half the
+/// hysteresis above the threshold and closes half below, so noise near the threshold doesn't
+/// make it chatter.
+#[derive(Default)]
+pub struct Comparator {
+    open: bool,
+}
+
+impl NodeProcessor for Comparator {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("input"),
+            Port::new("threshold", PortKind::Control).with_range(Range::BIPOLAR),
+            Port::new("hysteresis", PortKind::Control).with_range(HYSTERESIS),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("gate"), Port::audio("inverted")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, threshold, hysteresis] = inputs.split();
+        let (input, threshold) = (io.input(input), io.input(threshold));
+        let hysteresis = io.input(hysteresis);
+        let [gate, inverted] = io.outputs();
+        for i in 0..gate.len() {
+            let margin = hysteresis.get(i).max(0.0) * 0.5;
+            let input = input.get(i);
+            if input > threshold.get(i) + margin {
+                self.open = true;
+            } else if input < threshold.get(i) - margin {
+                self.open = false;
+            }
+            gate[i] = self.open as u8 as f32;
+            inverted[i] = 1.0 - gate[i];
+        }
+    }
+
+    fn reset(&mut self) {
+        self.open = false;
+    }
+
+    fn save(&self) -> Vec<f32> {
+        vec![self.open as u8 as f32]
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let [open] = *state {
+            self.open = open != 0.0;
+        }
+    }
+}
+
+/// The input negated, and its sign: -1, 0 or 1.
+#[derive(Default)]
+pub struct Invert;
+
+impl NodeProcessor for Invert {
+    fn inputs(&self) -> Ports {
+        Ports::from([Port::audio("input")])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("inverted"), Port::audio("sign")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input] = inputs.split();
+        let input = io.input(input);
+        let [inverted, sign] = io.outputs();
+        for i in 0..inverted.len() {
+            let input = input.get(i);
+            inverted[i] = -input;
+            sign[i] = if input == 0.0 { 0.0 } else { input.signum() };
+        }
+    }
+}
+
+/// Limits the input to between `min` and `max`. If they cross, `max` wins.
+#[derive(Default)]
+pub struct Clamp;
+
+impl NodeProcessor for Clamp {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("input"),
+            Port::new("min", PortKind::Control).with_range(Range::BIPOLAR.with_default(-1.0)),
+            Port::new("max", PortKind::Control).with_range(Range::BIPOLAR.with_default(1.0)),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, min, max] = inputs.split();
+        let (input, min, max) = (io.input(input), io.input(min), io.input(max));
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            *output = input.get(i).max(min.get(i)).min(max.get(i));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    /// The first sample of each of `processor`'s outputs, with inputs set to `values`.
+    fn outputs(processor: &mut dyn NodeProcessor, values: &[(&str, f32)]) -> Vec<f32> {
+        let mut inputs = processor.inputs();
+        for &(name, value) in values {
+            let port = inputs.named_mut(name).unwrap();
+            *port = port.with_value(value);
+        }
+        render_processor(processor, &mut inputs, 1, SAMPLE_RATE)
+            .iter()
+            .map(|output| output[0])
+            .collect()
+    }
+
+    #[test]
+    fn mixer_sums_each_input_at_its_gain() {
+        assert_eq!(Mixer::<2>.inputs().len(), 4);
+        assert_eq!(Mixer::<4>.inputs().len(), 8);
+        let mut values = Vec::new();
+        for (i, (input, gain)) in MIXER_INPUTS.iter().zip(MIXER_GAINS).enumerate() {
+            values.push((*input, (i + 1) as f32 * 0.125));
+            values.push((gain, if i % 2 == 0 { 2.0 } else { 0.0 }));
+        }
+        // 2 × (1 + 3 + 5 + 7) / 8
+        assert_eq!(outputs(&mut Mixer::<8>, &values), [4.0]);
+    }
+
+    #[test]
+    fn scale_multiplies_then_offsets() {
+        let values = [("input", 0.5), ("scale", -2.0), ("offset", 0.25)];
+        assert_eq!(outputs(&mut Scale, &values), [-0.75]);
+    }
+
+    #[test]
+    fn crossfade_curves() {
+        let fade = |mix, equal_power| {
+            let values = [
+                ("a", 1.0),
+                ("b", 1.0),
+                ("mix", mix),
+                ("equal power", equal_power),
+            ];
+            outputs(&mut Crossfade, &values)[0]
+        };
+        for equal_power in [0.0, 1.0] {
+            assert_eq!(fade(0.0, equal_power), 1.0);
+            assert!((fade(1.0, equal_power) - 1.0).abs() < 1e-6);
+        }
+        assert_eq!(fade(0.5, 0.0), 1.0);
+        assert!((fade(0.5, 1.0) - std::f32::consts::SQRT_2).abs() < 1e-6);
+        let values = [("a", 1.0), ("b", 0.0), ("mix", 0.25)];
+        assert_eq!(outputs(&mut Crossfade, &values), [0.75]);
+    }
+
+    #[test]
+    fn min_max_sorts_its_inputs() {
+        let values = [("input 1", 0.3), ("input 2", -0.2)];
+        assert_eq!(outputs(&mut MinMax, &values), [-0.2, 0.3]);
+    }
+
+    #[test]
+    fn comparator_hysteresis_ignores_small_wobbles() {
+        let mut comparator = Comparator::default();
+        let mut inputs = comparator.inputs();
+        inputs[2] = inputs[2].with_value(0.2);
+        let signal = [0.0, 0.2, 0.05, -0.05, -0.2, 0.05, 0.2];
+        let output = process_signal(&mut comparator, &mut inputs, &signal, SAMPLE_RATE);
+        assert_eq!(output[0], [0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(output[1], [1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn invert_negates_and_takes_the_sign() {
+        let mut invert = Invert;
+        let mut inputs = invert.inputs();
+        let output = process_signal(&mut invert, &mut inputs, &[-2.0, 0.0, 0.5], SAMPLE_RATE);
+        assert_eq!(output[0], [2.0, 0.0, -0.5]);
+        assert_eq!(output[1], [-1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn clamp_limits_and_max_wins_when_crossed() {
+        let clamp = |input, min, max| {
+            outputs(&mut Clamp, &[("input", input), ("min", min), ("max", max)])[0]
+        };
+        assert_eq!(clamp(2.0, -1.0, 1.0), 1.0);
+        assert_eq!(clamp(-2.0, -1.0, 1.0), -1.0);
+        assert_eq!(clamp(0.25, -1.0, 1.0), 0.25);
+        assert_eq!(clamp(0.0, 0.5, -0.5), -0.5);
+        assert_eq!(clamp(1.0, 0.5, -0.5), -0.5);
+    }
+}
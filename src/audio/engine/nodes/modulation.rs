@@ -0,0 +1,296 @@
+//! Effects that sweep a delay or a filter with their own LFO.
+
+use super::*;
+use crate::audio::engine::dsp::{DelayLine, FirstOrderAllpass, Phasor};
+
+const RATE: Range = Range::new(0.01, 10.0, 0.5, Unit::Hz, Curve::Logarithmic);
+const FEEDBACK: Range = Range::new(-0.95, 0.95, 0.0, Unit::Ratio, Curve::Linear);
+
+fn rate_and_depth() -> [Port; 2] {
+    [
+        Port::new("rate", PortKind::Control).with_range(RATE),
+        Port::new("depth", PortKind::Control).with_range(LEVEL.with_default(0.5)),
+    ]
+}
+
+const MAX_VOICES: usize = 4;
+const VOICES: Range = Range::new(1.0, MAX_VOICES as f32, 2.0, Unit::None, Curve::Linear);
+/// Chorus voices swing by up to `CHORUS_SWING` either side of `CHORUS_DELAY`.
+const CHORUS_DELAY: f32 = 0.015;
+const CHORUS_SWING: f32 = 0.005;
+
+/// Copies of the input, each delayed by a slowly wandering amount, spread evenly around the
+/// LFO's cycle.
+pub struct Chorus {
+    line: DelayLine,
+    lfo: Phasor,
+}
+
+impl Default for Chorus {
+    fn default() -> Chorus {
+        Chorus {
+            line: DelayLine::with_seconds(CHORUS_DELAY + CHORUS_SWING),
+            lfo: Phasor::default(),
+        }
+    }
+}
+
+impl NodeProcessor for Chorus {
+    fn inputs(&self) -> Ports {
+        let [rate, depth] = rate_and_depth();
+        Ports::from([
+            Port::audio("input"),
+            rate,
+            depth,
+            Port::new("voices", PortKind::Constant).with_range(VOICES),
+            Port::new("mix", PortKind::Control).with_range(LEVEL.with_default(0.5)),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, rate, depth, voices, mix] = inputs.split();
+        let (input, rate, depth) = (io.input(input), io.input(rate), io.input(depth));
+        let (voices, mix) = (io.input(voices), io.input(mix));
+        let voices = voices.get(0).round().clamp(1.0, MAX_VOICES as f32);
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let x = input.get(i);
+            self.line.push(x);
+            let swing = CHORUS_SWING * depth.get(i);
+            let mut wet = 0.0;
+            for voice in 0..voices as usize {
+                let offset = voice as f32 / voices;
+                let delay = CHORUS_DELAY + swing * self.lfo.sine(offset);
+                wet += self.line.read(delay * sample_rate);
+            }
+            let mix = mix.get(i);
+            *output = x * (1.0 - mix) + wet / voices * mix;
+            self.lfo.advance(rate.get(i), sample_rate);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.lfo = Phasor::default();
+    }
+}
+
+/// A flanger sweeps a single short delay between these.
+const FLANGER_MIN: f32 = 0.001;
+const FLANGER_SWING: f32 = 0.005;
+
+/// A short delay swept up and down and fed back on itself, which moves a comb of notches
+/// through the spectrum.
+pub struct Flanger {
+    line: DelayLine,
+    lfo: Phasor,
+}
+
+impl Default for Flanger {
+    fn default() -> Flanger {
+        Flanger {
+            line: DelayLine::with_seconds(FLANGER_MIN + FLANGER_SWING),
+            lfo: Phasor::default(),
+        }
+    }
+}
+
+impl NodeProcessor for Flanger {
+    fn inputs(&self) -> Ports {
+        let [rate, depth] = rate_and_depth();
+        Ports::from([
+            Port::audio("input"),
+            rate,
+            depth,
+            Port::new("feedback", PortKind::Control).with_range(FEEDBACK.with_default(0.5)),
+            Port::new("mix", PortKind::Control).with_range(LEVEL.with_default(0.5)),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, rate, depth, feedback, mix] = inputs.split();
+        let (input, rate, depth) = (io.input(input), io.input(rate), io.input(depth));
+        let (feedback, mix) = (io.input(feedback), io.input(mix));
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let x = input.get(i);
+            let sweep = 0.5 + 0.5 * self.lfo.sine(0.0);
+            let delay = FLANGER_MIN + FLANGER_SWING * depth.get(i) * sweep;
+            let delayed = self.line.read(delay * sample_rate);
+            let feedback = feedback.get(i).clamp(FEEDBACK.min, FEEDBACK.max);
+            self.line.push(x + delayed * feedback);
+            let mix = mix.get(i);
+            *output = x * (1.0 - mix) + delayed * mix;
+            self.lfo.advance(rate.get(i), sample_rate);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.lfo = Phasor::default();
+    }
+}
+
+const MAX_STAGES: usize = 12;
+const STAGES: Range = Range::new(2.0, MAX_STAGES as f32, 4.0, Unit::None, Curve::Linear);
+/// A phaser sweeps its all-passes between these, in Hz.
+const PHASER_MIN: f32 = 100.0;
+const PHASER_MAX: f32 = 4000.0;
+
+/// A chain of all-passes swept together. Mixed with the dry signal, every two stages make a
+/// notch that moves with the sweep.
+#[derive(Default)]
+pub struct Phaser {
+    stages: [FirstOrderAllpass; MAX_STAGES],
+    lfo: Phasor,
+    /// The last output, for feedback.
+    last: f32,
+}
+
+impl NodeProcessor for Phaser {
+    fn inputs(&self) -> Ports {
+        let [rate, depth] = rate_and_depth();
+        Ports::from([
+            Port::audio("input"),
+            rate,
+            depth,
+            Port::new("stages", PortKind::Constant).with_range(STAGES),
+            Port::new("feedback", PortKind::Control).with_range(FEEDBACK),
+            Port::new("mix", PortKind::Control).with_range(LEVEL.with_default(0.5)),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, rate, depth, stages, feedback, mix] = inputs.split();
+        let (input, rate, depth) = (io.input(input), io.input(rate), io.input(depth));
+        let (stages, feedback, mix) = (io.input(stages), io.input(feedback), io.input(mix));
+        // Odd counts don't make a whole notch.
+        let stages = ((stages.get(0) / 2.0).round() * 2.0).clamp(STAGES.min, STAGES.max);
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let x = input.get(i);
+            let sweep = depth.get(i) * (0.5 + 0.5 * self.lfo.sine(0.0));
+            let frequency = PHASER_MIN * (PHASER_MAX / PHASER_MIN).powf(sweep);
+            let coefficient = FirstOrderAllpass::coefficient(frequency, sample_rate);
+            let feedback = feedback.get(i).clamp(FEEDBACK.min, FEEDBACK.max);
+            let mut wet = x + self.last * feedback;
+            for stage in &mut self.stages[..stages as usize] {
+                wet = stage.process(wet, coefficient);
+            }
+            self.last = wet;
+            let mix = mix.get(i);
+            *output = x * (1.0 - mix) + wet * mix;
+            self.lfo.advance(rate.get(i), sample_rate);
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Phaser::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+    const LENGTH: usize = 9600;
+
+    fn sine(frequency: f32) -> Vec<f32> {
+        (0..LENGTH)
+            .map(|n| (TAU * frequency * n as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    /// The wet output for `signal`, with the named inputs set.
+    fn wet(processor: &mut dyn NodeProcessor, signal: &[f32], values: &[(&str, f32)]) -> Vec<f32> {
+        let mut inputs = processor.inputs();
+        for &(port, value) in values.iter().chain(&[("mix", 1.0)]) {
+            let port = inputs.named_mut(port).unwrap();
+            *port = port.with_value(value);
+        }
+        process_signal(processor, &mut inputs, signal, SAMPLE_RATE).remove(0)
+    }
+
+    /// Whether `output` is `input` `delay` samples late, once the line has filled.
+    fn is_delayed(output: &[f32], input: &[f32], delay: usize) -> bool {
+        (delay..output.len()).all(|n| (output[n] - input[n - delay]).abs() < 1e-3)
+    }
+
+    #[test]
+    fn chorus_without_depth_is_a_fixed_delay() {
+        let input = sine(440.0);
+        for voices in 1..=MAX_VOICES {
+            let values = [("depth", 0.0), ("rate", 10.0), ("voices", voices as f32)];
+            let output = wet(&mut Chorus::default(), &input, &values);
+            // It reads after pushing, so the latest sample is a delay of 1.
+            let delay = (CHORUS_DELAY * SAMPLE_RATE as f32).round() as usize - 1;
+            assert!(is_delayed(&output, &input, delay), "{} voices", voices);
+        }
+    }
+
+    #[test]
+    fn flanger_without_depth_is_a_fixed_delay() {
+        let input = sine(440.0);
+        let values = [("depth", 0.0), ("rate", 10.0), ("feedback", 0.0)];
+        let output = wet(&mut Flanger::default(), &input, &values);
+        let delay = (FLANGER_MIN * SAMPLE_RATE as f32).round() as usize;
+        assert!(is_delayed(&output, &input, delay));
+    }
+
+    #[test]
+    fn phaser_without_depth_stands_still() {
+        let input = sine(440.0);
+        let output = |rate| {
+            wet(
+                &mut Phaser::default(),
+                &input,
+                &[("depth", 0.0), ("rate", rate)],
+            )
+        };
+        let slow = output(0.01);
+        assert_eq!(slow, output(10.0));
+        // All-passes leave the level alone.
+        let rms = |signal: &[f32]| {
+            let settled = &signal[signal.len() / 2..];
+            (settled.iter().map(|x| x * x).sum::<f32>() / settled.len() as f32).sqrt()
+        };
+        assert!((rms(&slow) / rms(&input) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn output_stays_finite() {
+        let square: Vec<f32> = sine(110.0).iter().map(|x| x.signum()).collect();
+        let full = [("depth", 1.0), ("rate", RATE.max)];
+        let bounded = |output: Vec<f32>| output.iter().all(|x| x.is_finite() && x.abs() < 100.0);
+        assert!(bounded(wet(&mut Chorus::default(), &square, &full)));
+        for feedback in [FEEDBACK.min, FEEDBACK.max] {
+            let values = [full[0], full[1], ("feedback", feedback)];
+            assert!(bounded(wet(&mut Flanger::default(), &square, &values)));
+            let values = [
+                full[0],
+                full[1],
+                ("feedback", feedback),
+                ("stages", STAGES.max),
+            ];
+            assert!(bounded(wet(&mut Phaser::default(), &square, &values)));
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use super::*;
+use crate::audio::engine::dsp::Random;
+
+/// 0 is white, 1 pink and 2 brown, blending in between.
+const COLOUR: Range = Range::new(0.0, 2.0, 0.0, Unit::None, Curve::Linear);
+const SEED: Range = Range::new(1.0, 65535.0, 1.0, Unit::None, Curve::Linear);
+
+/// Scales pink and brown noise to about the same RMS level as white.
+const PINK_GAIN: f32 = 0.34;
+const BROWN_GAIN: f32 = 10.0;
+
+/// White, pink and brown noise from a seeded generator, so the same seed always gives the
+/// same noise from a reset. Changing `seed` starts the sequence over.
+pub struct Noise {
+    random: Random,
+    seed: f32,
+    /// Kellet's three-pole approximation of a -3 dB per octave slope.
+    pink: [f32; 3],
+    brown: f32,
+}
+
+impl Default for Noise {
+    fn default() -> Noise {
+        Noise {
+            random: Random::new(SEED.default as u32),
+            seed: SEED.default,
+            pink: [0.0; 3],
+            brown: 0.0,
+        }
+    }
+}
+
+impl NodeProcessor for Noise {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::new("level", PortKind::Control).with_range(LEVEL.with_default(1.0)),
+            Port::new("colour", PortKind::Control).with_range(COLOUR),
+            Port::new("seed", PortKind::Constant).with_range(SEED),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("output"),
+            Port::audio("white"),
+            Port::audio("pink"),
+            Port::audio("brown"),
+        ])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [level, colour, seed] = inputs.split();
+        let (level, colour, seed) = (io.input(level), io.input(colour), io.input(seed));
+        if seed.get(0) != self.seed {
+            *self = Noise {
+                random: Random::new(seed.get(0) as u32),
+                seed: seed.get(0),
+                ..Noise::default()
+            };
+        }
+        let [output, white, pink, brown] = io.outputs();
+        let [b0, b1, b2] = &mut self.pink;
+        for i in 0..output.len() {
+            let w = self.random.next_bipolar();
+            *b0 = 0.99765 * *b0 + w * 0.0990460;
+            *b1 = 0.96300 * *b1 + w * 0.2965164;
+            *b2 = 0.57000 * *b2 + w * 1.0526913;
+            let p = (*b0 + *b1 + *b2 + w * 0.1848) * PINK_GAIN;
+            self.brown = (self.brown + 0.02 * w) / 1.02;
+            let b = self.brown * BROWN_GAIN;
+
+            let colour = colour.get(i).clamp(0.0, 2.0);
+            let mixed = if colour < 1.0 {
+                w + (p - w) * colour
+            } else {
+                p + (b - p) * (colour - 1.0)
+            };
+            let level = level.get(i);
+            output[i] = mixed * level;
+            white[i] = w * level;
+            pink[i] = p * level;
+            brown[i] = b * level;
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Noise {
+            random: Random::new(self.seed as u32),
+            seed: self.seed,
+            ..Noise::default()
+        };
+    }
+
+    fn save(&self) -> Vec<f32> {
+        let [high, low] = self.random.save();
+        let [b0, b1, b2] = self.pink;
+        vec![high, low, self.seed, b0, b1, b2, self.brown]
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let [high, low, seed, b0, b1, b2, brown] = *state {
+            self.random.restore([high, low]);
+            self.seed = seed;
+            self.pink = [b0, b1, b2];
+            self.brown = brown;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    fn render(noise: &mut Noise, seed: f32) -> Vec<Vec<f32>> {
+        let mut inputs = noise.inputs();
+        inputs[2] = inputs[2].with_value(seed);
+        render_processor(noise, &mut inputs, 1000, SAMPLE_RATE)
+    }
+
+    #[test]
+    fn seed_makes_renders_repeatable() {
+        let first = render(&mut Noise::default(), 42.0);
+        assert_eq!(render(&mut Noise::default(), 42.0), first);
+
+        let mut noise = Noise::default();
+        render(&mut noise, 42.0);
+        noise.reset();
+        assert_eq!(render(&mut noise, 42.0), first);
+
+        let other = render(&mut Noise::default(), 43.0);
+        for (output, other) in first.iter().zip(&other) {
+            assert_ne!(output, other);
+        }
+    }
+}
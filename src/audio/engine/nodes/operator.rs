@@ -0,0 +1,300 @@
+use super::*;
+use crate::audio::engine::{dsp::Phasor, GATE_SLOT, PITCH_SLOT, TRIGGER_SLOT};
+use adsr::{Adsr, Segments};
+
+/// 0 to follow `frequency` times `ratio`, 1 to stay at `fixed frequency`.
+const FIXED: Range = Range::new(0.0, 1.0, 0.0, Unit::None, Curve::Linear);
+const RATIO: Range = Range::new(0.125, 32.0, 1.0, Unit::Ratio, Curve::Logarithmic);
+/// How far a full-scale signal at `phase mod` pushes the phase, in cycles.
+const MODULATION_DEPTH: f32 = 2.0;
+/// How far the operator's own output pushes its phase at full feedback, in cycles.
+const FEEDBACK_DEPTH: f32 = 0.25;
+
+/// A sine with its own envelope, for building FM voices. `phase mod` offsets the phase rather
+/// than the frequency, so modulation doesn't drift the pitch, and `mix in` is added to the
+/// output, so operators can be chained to sum without a mixer. Feedback averages the last two
+/// outputs, which keeps high settings noisy instead of letting them oscillate.
+#[derive(Default)]
+pub struct Operator {
+    phasor: Phasor,
+    previous: [f32; 2],
+    envelope: Adsr,
+}
+
+impl NodeProcessor for Operator {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("frequency").with_range(Range::FREQUENCY),
+            Port::new("fixed", PortKind::Constant).with_range(FIXED),
+            Port::new("ratio", PortKind::Control).with_range(RATIO),
+            Port::new("fixed frequency", PortKind::Control).with_range(Range::FREQUENCY),
+            Port::new("level", PortKind::Control).with_range(LEVEL.with_default(1.0)),
+            Port::new("feedback", PortKind::Control).with_range(LEVEL),
+            Port::audio("phase mod").with_range(Range::BIPOLAR),
+            Port::audio("mix in").with_range(Range::BIPOLAR),
+            Port::new("attack", PortKind::Control).with_range(TIME.with_default(0.01)),
+            Port::new("decay", PortKind::Control).with_range(TIME.with_default(0.3)),
+            Port::new("sustain", PortKind::Control).with_range(LEVEL.with_default(1.0)),
+            Port::new("release", PortKind::Control).with_range(TIME.with_default(0.2)),
+            Port::audio("gate").with_range(Range::UNIPOLAR.with_default(1.0)),
+            Port::new("trigger", PortKind::Constant).with_range(Range::UNIPOLAR),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [frequency, fixed, ratio, fixed_frequency, level, feedback, phase_mod, mix_in, attack, decay, sustain, release, gate, trigger] =
+            inputs.split();
+        let (frequency, fixed, ratio) = (io.input(frequency), io.input(fixed), io.input(ratio));
+        let (fixed_frequency, level) = (io.input(fixed_frequency), io.input(level));
+        let (feedback, phase_mod, mix_in) =
+            (io.input(feedback), io.input(phase_mod), io.input(mix_in));
+        let (attack, decay) = (io.input(attack), io.input(decay));
+        let (sustain, release) = (io.input(sustain), io.input(release));
+        let (gate, trigger) = (io.input(gate), io.input(trigger));
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let segments = Segments {
+                attack: attack.get(i),
+                decay: decay.get(i),
+                sustain: sustain.get(i),
+                release: release.get(i),
+                exponential: true,
+            };
+            self.envelope.step(
+                gate.get(i) > 0.0,
+                trigger.get(i),
+                true,
+                1.0,
+                segments,
+                sample_rate,
+            );
+            let [last, before] = self.previous;
+            let offset = phase_mod.get(i) * MODULATION_DEPTH
+                + feedback.get(i) * FEEDBACK_DEPTH * (last + before) * 0.5;
+            // Fed back, a NaN would never leave.
+            let offset = if offset.is_finite() { offset } else { 0.0 };
+            let sample = self.phasor.sine(offset) * level.get(i) * self.envelope.level();
+            self.previous = [sample, last];
+            let frequency = if fixed.get(i) >= 0.5 {
+                fixed_frequency.get(i)
+            } else {
+                frequency.get(i) * ratio.get(i)
+            };
+            self.phasor.advance(frequency, sample_rate);
+            *output = sample + mix_in.get(i);
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Operator::default();
+    }
+
+    fn save(&self) -> Vec<f32> {
+        let mut state = vec![self.phasor.phase, self.previous[0], self.previous[1]];
+        state.extend(self.envelope.save());
+        state
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let [phase, last, before, ref envelope @ ..] = *state {
+            self.phasor.phase = phase;
+            self.previous = [last, before];
+            self.envelope.restore(envelope);
+        }
+    }
+}
+
+/// A classic FM routing, inserted as one `Operator` node per operator. Operators are numbered
+/// from 1 and only modulate lower-numbered ones, so they're processed from the highest down.
+#[derive(Debug)]
+pub struct Algorithm {
+    pub name: &'static str,
+    /// The operators modulating each operator, from operator 1 up. Where there's more than
+    /// one, they're summed through each other's `mix in`, so every one but the lowest must
+    /// modulate only this operator.
+    modulators: &'static [&'static [u8]],
+    /// Summed the same way into the stack's output.
+    carriers: &'static [u8],
+    /// The operator that starts with some feedback.
+    feedback: u8,
+}
+
+/// The four-operator algorithms of the DX21 family, then a selection of the DX7's.
+pub static ALGORITHMS: [Algorithm; 14] = [
+    Algorithm::new("4-op 1", &[&[2], &[3], &[4], &[]], &[1], 4),
+    Algorithm::new("4-op 2", &[&[2], &[3, 4], &[], &[]], &[1], 4),
+    Algorithm::new("4-op 3", &[&[2, 4], &[3], &[], &[]], &[1], 4),
+    Algorithm::new("4-op 4", &[&[2, 3], &[], &[4], &[]], &[1], 4),
+    Algorithm::new("4-op 5", &[&[2], &[], &[4], &[]], &[1, 3], 4),
+    Algorithm::new("4-op 6", &[&[4], &[4], &[4], &[]], &[1, 2, 3], 4),
+    Algorithm::new("4-op 7", &[&[], &[], &[4], &[]], &[1, 2, 3], 4),
+    Algorithm::new("4-op 8", &[&[], &[], &[], &[]], &[1, 2, 3, 4], 4),
+    Algorithm::new("DX7 1", &[&[2], &[], &[4], &[5], &[6], &[]], &[1, 3], 6),
+    Algorithm::new("DX7 2", &[&[2], &[], &[4], &[5], &[6], &[]], &[1, 3], 2),
+    Algorithm::new("DX7 5", &[&[2], &[], &[4], &[], &[6], &[]], &[1, 3, 5], 6),
+    Algorithm::new("DX7 16", &[&[2, 3, 5], &[], &[4], &[], &[6], &[]], &[1], 6),
+    Algorithm::new(
+        "DX7 22",
+        &[&[2], &[], &[6], &[6], &[6], &[]],
+        &[1, 3, 4, 5],
+        6,
+    ),
+    Algorithm::new(
+        "DX7 32",
+        &[&[], &[], &[], &[], &[], &[]],
+        &[1, 2, 3, 4, 5, 6],
+        6,
+    ),
+];
+
+impl Algorithm {
+    const fn new(
+        name: &'static str,
+        modulators: &'static [&'static [u8]],
+        carriers: &'static [u8],
+        feedback: u8,
+    ) -> Algorithm {
+        Algorithm {
+            name,
+            modulators,
+            carriers,
+            feedback,
+        }
+    }
+
+    pub fn operators(&self) -> usize {
+        self.modulators.len()
+    }
+
+    /// The operators, in processing order, wired to each other through audio slots from
+    /// `first_slot` on and to the keyboard's control slots. The caller makes sure those slots
+    /// fit. The last carrier writes to the stack's output, and the carriers sum `mix_in` into
+    /// it, so the nodes can go after ones that already write there without replacing them.
+    pub fn nodes(&self, first_slot: u8, mix_in: Option<Slot>) -> Vec<Node> {
+        let kind = NodeKind::named("Operator").unwrap();
+        let slot = |operator: u8| Some(Slot::Audio(first_slot + operator - 1));
+        let mut nodes: Vec<Node> = (0..self.operators()).map(|_| Node::new(kind)).collect();
+        let mut connect = |operator: u8, port: &str, slot: Option<Slot>| {
+            let port = nodes[operator as usize - 1].inputs.named_mut(port).unwrap();
+            port.slot = slot;
+        };
+        for operator in 1..=self.operators() as u8 {
+            connect(operator, "frequency", Some(Slot::Control(PITCH_SLOT as u8)));
+            connect(operator, "gate", Some(Slot::Control(GATE_SLOT as u8)));
+            connect(operator, "trigger", Some(Slot::Control(TRIGGER_SLOT as u8)));
+        }
+        for (operator, modulators) in (1..).zip(self.modulators) {
+            let mut summed = None;
+            for &modulator in modulators.iter().rev() {
+                connect(modulator, "mix in", summed);
+                summed = slot(modulator);
+            }
+            connect(operator, "phase mod", summed);
+        }
+        let mut summed = mix_in;
+        for &carrier in self.carriers.iter().rev() {
+            connect(carrier, "mix in", summed);
+            summed = slot(carrier);
+        }
+        for (operator, node) in (1..).zip(&mut nodes) {
+            node.outputs[0].slot = slot(operator);
+            let carrier = self.carriers.contains(&operator);
+            let level = if carrier {
+                1.0 / self.carriers.len() as f32
+            } else {
+                1.0
+            };
+            let feedback = if operator == self.feedback { 0.3 } else { 0.0 };
+            for (port, value) in [("level", level), ("feedback", feedback)] {
+                let port = node.inputs.named_mut(port).unwrap();
+                *port = port.with_value(value);
+            }
+        }
+        if let Some(&last) = self.carriers.first() {
+            nodes[last as usize - 1].outputs[0].slot = Some(Slot::Audio(0));
+        }
+        nodes.reverse();
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrayvec::ArrayVec;
+    use basedrop::Collector;
+
+    use super::*;
+
+    #[test]
+    fn every_algorithm_plays() {
+        let collector = Collector::new();
+        let handle = collector.handle();
+        for algorithm in &ALGORITHMS {
+            let nodes = algorithm
+                .nodes(1, None)
+                .into_iter()
+                .map(|node| Owned::new(&handle, node))
+                .collect();
+            let mut stack = Stack::new(Owned::new(&handle, nodes), DEFAULT_BLOCK_SIZE);
+            stack.data.control[PITCH_SLOT] = 220.0;
+            stack.data.control[GATE_SLOT] = 1.0;
+            let mut output = vec![0.0; 4800];
+            stack.process(&mut output, 48000);
+            let peak = output
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            assert!(
+                peak > 0.1 && peak <= 1.0,
+                "{}: peak {}",
+                algorithm.name,
+                peak
+            );
+        }
+    }
+
+    #[test]
+    fn non_finite_modulation_holds_the_phase() {
+        let set = |inputs: &mut Ports, name, value| {
+            let port = inputs.named_mut(name).unwrap();
+            *port = port.with_value(value);
+        };
+        for (name, value) in [("frequency", f32::NAN), ("phase mod", f32::INFINITY)] {
+            let mut operator = Operator::default();
+            let mut inputs = operator.inputs();
+            set(&mut inputs, "feedback", 0.5);
+            let original = inputs.named(name).unwrap().value;
+            set(&mut inputs, name, value);
+            let mut output = render_processor(&mut operator, &mut inputs, 64, 48000).remove(0);
+            // Once the input recovers, so does the output.
+            set(&mut inputs, name, original);
+            output.extend(render_processor(&mut operator, &mut inputs, 64, 48000).remove(0));
+            assert!(output.iter().all(|sample| sample.is_finite()), "{}", name);
+            assert!(operator.phasor.phase.is_finite(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn algorithms_add_to_the_existing_output() {
+        let collector = Collector::new();
+        let handle = collector.handle();
+        let mut existing = Node::new(NodeKind::named("Scale").unwrap());
+        let offset = existing.inputs.named_mut("offset").unwrap();
+        *offset = offset.with_value(0.5);
+        existing.outputs[0].slot = Some(Slot::Audio(0));
+        let mut nodes = ArrayVec::<Owned<Node>, MAX_NODES>::new();
+        nodes.push(Owned::new(&handle, existing));
+        for node in ALGORITHMS[0].nodes(1, Some(Slot::Audio(0))) {
+            nodes.push(Owned::new(&handle, node));
+        }
+        let mut stack = Stack::new(Owned::new(&handle, nodes), DEFAULT_BLOCK_SIZE);
+        // With the gate closed the operators are silent, leaving what was there already.
+        let mut output = vec![0.0; DEFAULT_BLOCK_SIZE];
+        stack.process(&mut output, 48000);
+        assert!(output.iter().all(|&sample| sample == 0.5));
+    }
+}
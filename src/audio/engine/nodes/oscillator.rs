@@ -0,0 +1,240 @@
+use super::*;
+use crate::audio::engine::dsp::{poly_blamp, poly_blep};
+
+/// `pitch` scales `frequency` by a power of two, then `fm` adds to it, so it can go through
+/// zero and run backwards. `phase` offsets where the waveform is read, in cycles, and a rising
+/// edge through zero at `sync` restarts the cycle. Sync restarts aren't band-limited.
+#[derive(Default)]
+pub struct Oscillator {
+    phase: f32,
+    previous_sync: f32,
+}
+
+const PITCH: Range = Range::new(-5.0, 5.0, 0.0, Unit::Octaves, Curve::Linear);
+const FM: Range = Range::new(-10000.0, 10000.0, 0.0, Unit::Hz, Curve::Linear);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Waveform {
+    Sawtooth,
+    Pulse,
+    Triangle,
+    Sine,
+}
+
+impl Waveform {
+    const RANGE: Range = Range::new(0.0, 3.0, 0.0, Unit::None, Curve::Linear);
+
+    fn from_value(value: f32) -> Waveform {
+        match value.round() as i32 {
+            i32::MIN..=0 => Waveform::Sawtooth,
+            1 => Waveform::Pulse,
+            2 => Waveform::Triangle,
+            _ => Waveform::Sine,
+        }
+    }
+}
+
+impl Oscillator {
+    /// One band-limited sample at `phase`, which moves by `increment` per sample.
+    fn sample(waveform: Waveform, phase: f32, increment: f32, pulse_width: f32) -> f32 {
+        match waveform {
+            Waveform::Sawtooth => phase * 2.0 - 1.0 - poly_blep(phase, increment),
+            Waveform::Pulse => {
+                let pulse_width = pulse_width.clamp(increment, 1.0 - increment);
+                let naive = if phase < pulse_width { 1.0 } else { -1.0 };
+                let falling = (phase - pulse_width).rem_euclid(1.0);
+                naive + poly_blep(phase, increment) - poly_blep(falling, increment)
+            }
+            Waveform::Triangle => {
+                // The slope rises by 8 per cycle at the trough, phase 0, and falls by as much
+                // at the peak.
+                let naive = 1.0 - 4.0 * (phase - 0.5).abs();
+                let peak = (phase - 0.5).rem_euclid(1.0);
+                let corners = poly_blamp(phase, increment) - poly_blamp(peak, increment);
+                naive + 4.0 * increment * corners
+            }
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+        }
+    }
+}
+
+impl NodeProcessor for Oscillator {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("frequency").with_range(Range::FREQUENCY),
+            Port::new("waveform", PortKind::Constant).with_range(Waveform::RANGE),
+            Port::new("pulse width", PortKind::Control)
+                .with_range(Range::UNIPOLAR.with_default(0.5)),
+            Port::audio("pitch").with_range(PITCH),
+            Port::audio("fm").with_range(FM),
+            Port::audio("phase"),
+            Port::audio("sync"),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [frequency, waveform, pulse_width, pitch, fm, phase, sync] = inputs.split();
+        let (frequency, waveform) = (io.input(frequency), io.input(waveform));
+        let (pulse_width, pitch, fm) = (io.input(pulse_width), io.input(pitch), io.input(fm));
+        let (phase, sync) = (io.input(phase), io.input(sync));
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let waveform = Waveform::from_value(waveform.get(i));
+            let frequency = frequency.get(i) * pitch.get(i).exp2() + fm.get(i);
+            // A NaN would stick in the phase and trip the pulse width clamp on every sample.
+            let increment = frequency / sample_rate;
+            let increment = if increment.is_finite() {
+                increment.clamp(-0.5, 0.5)
+            } else {
+                0.0
+            };
+            let sync = sync.get(i);
+            if self.previous_sync <= 0.0 && sync > 0.0 {
+                // Start from where the cycle would be had it restarted at the crossing.
+                let since_crossing = sync / (sync - self.previous_sync);
+                self.phase = (increment * since_crossing).rem_euclid(1.0);
+            }
+            self.previous_sync = sync;
+            let read = (self.phase + phase.get(i)).rem_euclid(1.0);
+            *output = Oscillator::sample(waveform, read, increment.abs(), pulse_width.get(i));
+            self.phase = (self.phase + increment).rem_euclid(1.0);
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Oscillator::default();
+    }
+
+    fn save(&self) -> Vec<f32> {
+        vec![self.phase, self.previous_sync]
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let [phase, previous_sync] = *state {
+            self.phase = phase;
+            self.previous_sync = previous_sync;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+    const LENGTH: usize = 4096;
+    /// The fundamental is exactly this many DFT bins, so every harmonic and every alias lands
+    /// on a bin of its own and a plain DFT separates them.
+    const FUNDAMENTAL_BIN: usize = 150;
+
+    fn render(waveform: Waveform) -> Vec<f32> {
+        let mut oscillator = Oscillator::default();
+        let mut inputs = oscillator.inputs();
+        let frequency = (FUNDAMENTAL_BIN * SAMPLE_RATE) as f32 / LENGTH as f32;
+        let value = waveform as usize as f32;
+        inputs[0] = inputs[0].with_value(frequency);
+        inputs[1] = inputs[1].with_value(value);
+        inputs[2] = inputs[2].with_value(0.3);
+        render_processor(&mut oscillator, &mut inputs, LENGTH, SAMPLE_RATE).remove(0)
+    }
+
+    /// The naive waveform, for comparison.
+    fn render_naive(waveform: Waveform) -> Vec<f32> {
+        let increment = FUNDAMENTAL_BIN as f32 / LENGTH as f32;
+        (0..LENGTH)
+            .map(|i| {
+                let phase = (i as f32 * increment).fract();
+                match waveform {
+                    Waveform::Sawtooth => phase * 2.0 - 1.0,
+                    Waveform::Pulse if phase < 0.3 => 1.0,
+                    Waveform::Pulse => -1.0,
+                    Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+                    Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn band_limited_waveforms_alias_less() {
+        for waveform in [Waveform::Sawtooth, Waveform::Pulse, Waveform::Triangle] {
+            let naive = aliasing(&render_naive(waveform), FUNDAMENTAL_BIN);
+            let band_limited = aliasing(&render(waveform), FUNDAMENTAL_BIN);
+            assert!(
+                band_limited < naive - 10.0,
+                "{:?}: naive {:.1} dB, band-limited {:.1} dB",
+                waveform,
+                naive,
+                band_limited
+            );
+        }
+    }
+
+    #[test]
+    fn sine_is_pure() {
+        assert!(aliasing(&render(Waveform::Sine), FUNDAMENTAL_BIN) < -100.0);
+    }
+
+    #[test]
+    fn non_finite_frequency_holds_the_phase() {
+        let mut oscillator = Oscillator::default();
+        let mut inputs = oscillator.inputs();
+        inputs[0] = inputs[0].with_value(f32::NAN);
+        inputs[1] = inputs[1].with_value(Waveform::Pulse as usize as f32);
+        let output = render_processor(&mut oscillator, &mut inputs, 64, SAMPLE_RATE).remove(0);
+        assert!(output.iter().all(|sample| sample.is_finite()));
+    }
+
+    /// A hundredth of a cycle a sample.
+    const SINE_FREQUENCY: f32 = SAMPLE_RATE as f32 / 100.0;
+
+    /// A sine at `SINE_FREQUENCY`, with the named input connected to a signal if there's one.
+    fn render_sine(connected: Option<(&str, &[f32])>, length: usize) -> Vec<f32> {
+        let mut oscillator = Oscillator::default();
+        let mut inputs = oscillator.inputs();
+        inputs[0] = inputs[0].with_value(SINE_FREQUENCY);
+        inputs[1] = inputs[1].with_value(Waveform::Sine as usize as f32);
+        let signals: Vec<&[f32]> = connected.iter().map(|&(_, signal)| signal).collect();
+        if let Some((input, _)) = connected {
+            inputs.named_mut(input).unwrap().slot = Some(Slot::Audio(1));
+        }
+        super::render(&mut oscillator, &mut inputs, &signals, length, SAMPLE_RATE).remove(0)
+    }
+
+    #[test]
+    fn sync_restarts_on_rising_edges() {
+        // Rising edges halfway between samples 74 and 75, 224 and 225 and so on.
+        let sync: Vec<f32> = (0..1000)
+            .map(|n| if n % 150 < 75 { -1.0 } else { 1.0 })
+            .collect();
+        let output = render_sine(Some(("sync", &sync)), sync.len());
+        for (n, &sample) in output.iter().enumerate() {
+            let cycles = match n.checked_sub(75) {
+                Some(since) => 0.005 + 0.01 * (since % 150) as f32,
+                None => 0.01 * n as f32,
+            };
+            let expected = (cycles * std::f32::consts::TAU).sin();
+            assert!(
+                (sample - expected).abs() < 1e-3,
+                "{}: {} {}",
+                n,
+                sample,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn fm_without_depth_changes_nothing() {
+        let modulator: Vec<f32> = (0..1000).map(|n| (n as f32 * 0.3).sin()).collect();
+        let scaled = |depth: f32| modulator.iter().map(|x| x * depth).collect::<Vec<_>>();
+        let unmodulated = render_sine(None, 1000);
+        assert_eq!(render_sine(Some(("fm", &scaled(0.0))), 1000), unmodulated);
+        assert_ne!(render_sine(Some(("fm", &scaled(100.0))), 1000), unmodulated);
+    }
+}
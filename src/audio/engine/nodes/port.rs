@@ -1,12 +1,63 @@
+use std::fmt;
+
 use arrayvec::ArrayVec;
 
-use crate::audio::engine::StackData;
+use crate::audio::engine::{Audio, Control, StackData};
+
+/// The most ports any node has on one side. Every node carries room for this many inputs
+/// and outputs inline, so it's kept to what the largest node needs.
+pub const MAX_PORTS: usize = 16;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ports(ArrayVec<Port, MAX_PORTS>);
+
+impl Ports {
+    pub fn named(&self, name: &str) -> Result<&Port, UnknownPort> {
+        self.0
+            .iter()
+            .find(|port| port.name == name)
+            .ok_or_else(|| UnknownPort(name.to_owned()))
+    }
+
+    pub fn named_mut(&mut self, name: &str) -> Result<&mut Port, UnknownPort> {
+        self.0
+            .iter_mut()
+            .find(|port| port.name == name)
+            .ok_or_else(|| UnknownPort(name.to_owned()))
+    }
+
+    pub fn position(&self, name: &str) -> Result<usize, UnknownPort> {
+        self.0
+            .iter()
+            .position(|port| port.name == name)
+            .ok_or_else(|| UnknownPort(name.to_owned()))
+    }
 
-#[derive(Clone, Debug, Default)]
-pub struct Ports(ArrayVec<Port, 32>);
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.0.iter().map(|port| port.name)
+    }
+
+    /// The first `N` ports, for destructuring in the order they were declared.
+    pub fn split<const N: usize>(&mut self) -> [&mut Port; N] {
+        let mut ports = self.0.iter_mut();
+        [(); N].map(|_| ports.next().unwrap())
+    }
+}
+
+impl<const N: usize> From<[Port; N]> for Ports {
+    fn from(ports: [Port; N]) -> Self {
+        Ports(ports.into_iter().collect())
+    }
+}
+
+impl FromIterator<Port> for Ports {
+    fn from_iter<I: IntoIterator<Item = Port>>(ports: I) -> Self {
+        Ports(ports.into_iter().collect())
+    }
+}
 
 impl std::ops::Deref for Ports {
-    type Target = ArrayVec<Port, 32>;
+    type Target = ArrayVec<Port, MAX_PORTS>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -19,14 +70,6 @@ impl std::ops::DerefMut for Ports {
     }
 }
 
-impl std::ops::Index<&'static str> for Ports {
-    type Output = Port;
-
-    fn index(&self, index: &'static str) -> &Self::Output {
-        self.0.iter().find(|elem| elem.name == index).unwrap()
-    }
-}
-
 impl std::ops::Index<usize> for Ports {
     type Output = Port;
 
@@ -35,119 +78,306 @@ impl std::ops::Index<usize> for Ports {
     }
 }
 
-impl std::ops::IndexMut<&'static str> for Ports {
-    fn index_mut(&mut self, index: &'static str) -> &mut Self::Output {
-        self.0.iter_mut().find(|elem| elem.name == index).unwrap()
-    }
-}
-
 impl std::ops::IndexMut<usize> for Ports {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         &mut self.0[index]
     }
 }
 
+/// Looking a port up by a name the node doesn't have.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnknownPort(pub String);
+
+impl fmt::Display for UnknownPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "There is no port named \"{}\".", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPort {}
+
+/// A named connection point on a node. A port doesn't hold any samples itself; it refers to
+/// a slot in the stack's buffers, or falls back to `value` when it isn't connected.
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Port {
     pub name: &'static str,
-    pub stack_index: Option<u8>,
+    pub slot: Option<Slot>,
     pub kind: PortKind,
+    pub range: Range,
+    pub value: f32,
+    /// Where the last block's smoothing ended up.
+    smoothed: f32,
 }
 
 impl Port {
     pub fn audio(name: &'static str) -> Self {
-        Port {
-            name,
-            stack_index: None,
-            kind: PortKind::Audio([0.0; 256]),
-        }
+        Port::new(name, PortKind::Audio)
     }
 
     pub fn control(name: &'static str, value: f32) -> Self {
-        Port {
-            name,
-            stack_index: None,
-            kind: PortKind::Control(value),
-        }
+        Port::new(name, PortKind::Control).with_value(value)
+    }
+
+    pub fn constant(name: &'static str, value: f32) -> Self {
+        Port::new(name, PortKind::Constant).with_value(value)
     }
 
     pub fn new(name: &'static str, kind: PortKind) -> Self {
         Port {
             name,
-            stack_index: None,
+            slot: None,
             kind,
+            range: Range::default(),
+            value: 0.0,
+            smoothed: 0.0,
         }
     }
 
-    pub fn read(&mut self, data: &StackData) {
-        if let Some(index) = self.stack_index {
-            match &mut self.kind {
-                PortKind::Audio(buf) => {
-                    *buf = data.audio[index as usize];
-                }
-                PortKind::Control(buf) => {
-                    *buf = data.control[index as usize];
-                }
-            }
+    /// Sets the range, and starts the port off at its default.
+    pub fn with_range(self, range: Range) -> Self {
+        Port { range, ..self }.with_value(range.default)
+    }
+
+    /// Connects the port to `slot` from the start, like an input that should follow one of
+    /// the slots the engine writes.
+    pub fn with_slot(self, slot: Slot) -> Self {
+        Port {
+            slot: Some(slot),
+            ..self
+        }
+    }
+
+    /// Sets the unconnected value without ramping to it.
+    pub fn with_value(self, value: f32) -> Self {
+        Port {
+            value,
+            smoothed: value,
+            ..self
         }
     }
 
-    pub fn write(&self, data: &mut StackData) {
-        if let Some(index) = self.stack_index {
-            match self.kind {
-                PortKind::Audio(audio) => {
-                    data.audio[index as usize].copy_from_slice(&audio);
-                }
-                PortKind::Control(control) => {
-                    data.control[index as usize] = control;
-                }
+    /// Ramps from where the previous block left off to `target` over this block.
+    fn smooth(&mut self, target: f32, samples: usize) -> Input<'static> {
+        let start = self.smoothed;
+        self.smoothed = target;
+        if start == target || samples == 0 {
+            Input::Constant(target)
+        } else {
+            Input::Ramp {
+                start,
+                step: (target - start) / samples as f32,
             }
         }
     }
 }
 
-impl std::ops::Index<usize> for Port {
-    type Output = f32;
+/// What values make sense for a port, and how to present them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Range {
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    pub unit: Unit,
+    pub curve: Curve,
+}
+
+impl Range {
+    /// A signal, or anything else without a more specific range.
+    pub const BIPOLAR: Range = Range::new(-1.0, 1.0, 0.0, Unit::None, Curve::Linear);
+    pub const UNIPOLAR: Range = Range::new(0.0, 1.0, 0.0, Unit::None, Curve::Linear);
+    pub const FREQUENCY: Range = Range::new(20.0, 20000.0, 440.0, Unit::Hz, Curve::Logarithmic);
+    pub const TEMPO: Range = Range::new(20.0, 300.0, 120.0, Unit::Bpm, Curve::Linear);
 
-    fn index(&self, index: usize) -> &f32 {
-        &self.kind[index]
+    pub const fn new(min: f32, max: f32, default: f32, unit: Unit, curve: Curve) -> Range {
+        Range {
+            min,
+            max,
+            default,
+            unit,
+            curve,
+        }
+    }
+
+    pub const fn with_default(self, default: f32) -> Range {
+        Range { default, ..self }
+    }
+
+    pub fn contains(&self, value: f32) -> bool {
+        self.min <= value && value <= self.max
+    }
+
+    /// Knob position, from 0 to 1, for `value`.
+    pub fn normalize(&self, value: f32) -> f32 {
+        let value = value.clamp(self.min, self.max);
+        let normalized = match self.curve {
+            Curve::Linear => (value - self.min) / (self.max - self.min),
+            Curve::Logarithmic => (value / self.min).ln() / (self.max / self.min).ln(),
+            Curve::Exponential => ((value - self.min) / (self.max - self.min)).cbrt(),
+        };
+        if normalized.is_finite() {
+            normalized
+        } else {
+            0.0
+        }
+    }
+
+    /// The value at knob position `normalized`, from 0 to 1.
+    pub fn denormalize(&self, normalized: f32) -> f32 {
+        let normalized = normalized.clamp(0.0, 1.0);
+        let value = match self.curve {
+            Curve::Linear => self.min + normalized * (self.max - self.min),
+            Curve::Logarithmic => self.min * (self.max / self.min).powf(normalized),
+            Curve::Exponential => self.min + normalized.powi(3) * (self.max - self.min),
+        };
+        value.clamp(self.min, self.max)
+    }
+
+    /// `value` in this range's unit, for display.
+    pub fn display(&self, value: f32) -> String {
+        match self.unit {
+            Unit::None | Unit::Ratio => format!("{:.2}", value),
+            Unit::Hz if value.abs() >= 1000.0 => format!("{:.2} kHz", value / 1000.0),
+            Unit::Hz => format!("{:.1} Hz", value),
+            Unit::Seconds if value.abs() < 1.0 => format!("{:.0} ms", value * 1000.0),
+            Unit::Seconds => format!("{:.2} s", value),
+            Unit::Decibels => format!("{:.1} dB", value),
+            Unit::Octaves => format!("{:+.2} oct", value),
+            Unit::Bpm => format!("{:.0} BPM", value),
+        }
     }
 }
 
-impl std::ops::IndexMut<usize> for Port {
-    fn index_mut(&mut self, index: usize) -> &mut f32 {
-        &mut self.kind[index]
+impl Default for Range {
+    fn default() -> Self {
+        Range::BIPOLAR
     }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Unit {
+    None,
+    Hz,
+    Seconds,
+    Decibels,
+    /// Pitch relative to something else, where 1 doubles the frequency.
+    Octaves,
+    /// Beats per minute.
+    Bpm,
+    /// A plain multiplier or proportion.
+    Ratio,
+}
+
+/// How knob travel maps onto a range.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// Equal knob travel multiplies the value by the same amount. The range must not include
+    /// zero.
+    Logarithmic,
+    /// Cubic, for fine control near the bottom of ranges that start at zero, like times.
+    Exponential,
+}
+
+/// A buffer in the stack that ports can be connected to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Slot {
+    Audio(u8),
+    Control(u8),
+}
+
+/// How often a port's value is allowed to change. Connecting ports of different kinds
+/// converts between them: audio read at control rate takes the last sample of each block,
+/// control read at audio rate holds its value for the block, and constants only look at the
+/// start of the block.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub enum PortKind {
-    Audio([f32; 256]),
-    Control(f32),
+    /// One value per sample.
+    #[default]
+    Audio,
+    /// One value per block, ramped to across the block.
+    Control,
+    /// One value per block, used as-is. For switches, like a waveform selector.
+    Constant,
 }
 
-impl std::ops::Index<usize> for PortKind {
-    type Output = f32;
+/// What a node sees through one of its input ports.
+#[derive(Copy, Clone, Debug)]
+pub enum Input<'a> {
+    Audio(&'a [f32]),
+    Ramp { start: f32, step: f32 },
+    Constant(f32),
+}
 
-    fn index(&self, index: usize) -> &f32 {
-        match self {
-            PortKind::Audio(audio) => &audio[index],
-            PortKind::Control(control) => control,
+impl Input<'_> {
+    #[inline]
+    pub fn get(&self, index: usize) -> f32 {
+        match *self {
+            Input::Audio(audio) => audio[index],
+            Input::Ramp { start, step } => start + step * (index + 1) as f32,
+            Input::Constant(value) => value,
         }
     }
 }
 
-impl std::ops::IndexMut<usize> for PortKind {
-    fn index_mut(&mut self, index: usize) -> &mut f32 {
-        match self {
-            PortKind::Audio(audio) => &mut audio[index],
-            PortKind::Control(control) => control,
+/// The stack's buffers as seen by a single node: its inputs borrow the slots directly, while
+/// its outputs go to scratch buffers which are swapped into their slots afterwards.
+pub struct NodeIo<'a> {
+    audio: &'a [Audio],
+    control: &'a [Control],
+    outputs: &'a mut [Audio],
+    samples: usize,
+    sample_rate: f32,
+}
+
+impl<'a> NodeIo<'a> {
+    pub fn new(data: &'a mut StackData, samples: usize, sample_rate: usize) -> NodeIo<'a> {
+        NodeIo {
+            audio: &data.audio,
+            control: &data.control,
+            outputs: &mut data.scratch,
+            samples,
+            sample_rate: sample_rate as f32,
         }
     }
-}
 
-impl Default for PortKind {
-    fn default() -> Self {
-        PortKind::Audio([0.0; 256])
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    pub fn input(&self, port: &mut Port) -> Input<'a> {
+        let (audio, control, samples) = (self.audio, self.control, self.samples);
+        let block = |index: u8| &audio[index as usize][..samples];
+        match (port.kind, port.slot) {
+            (PortKind::Audio, Some(Slot::Audio(index))) => Input::Audio(block(index)),
+            (PortKind::Audio, Some(Slot::Control(index))) => {
+                Input::Constant(control[index as usize])
+            }
+            (PortKind::Control, Some(Slot::Audio(index))) => match block(index).last() {
+                Some(&last) => port.smooth(last, samples),
+                None => Input::Constant(port.smoothed),
+            },
+            (PortKind::Control, Some(Slot::Control(index))) => {
+                port.smooth(control[index as usize], samples)
+            }
+            (PortKind::Constant, Some(Slot::Audio(index))) => {
+                Input::Constant(block(index).first().copied().unwrap_or(port.value))
+            }
+            (PortKind::Constant, Some(Slot::Control(index))) => {
+                Input::Constant(control[index as usize])
+            }
+            (PortKind::Audio | PortKind::Control, None) => port.smooth(port.value, samples),
+            (PortKind::Constant, None) => Input::Constant(port.value),
+        }
+    }
+
+    /// The first `N` output buffers, in the order the node lists its outputs.
+    pub fn outputs<const N: usize>(&mut self) -> [&mut [f32]; N] {
+        let samples = self.samples;
+        let mut outputs = self.outputs.iter_mut().map(|output| &mut output[..samples]);
+        [(); N].map(|_| outputs.next().unwrap())
     }
 }
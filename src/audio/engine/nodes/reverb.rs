@@ -0,0 +1,195 @@
+use super::*;
+use crate::audio::engine::dsp::{flush_denormal, Allpass, DelayLine, MAX_SAMPLE_RATE};
+
+/// Delay lengths from Freeverb, in samples at `TUNING_RATE`.
+const COMBS: [f32; 8] = [
+    1116.0, 1188.0, 1277.0, 1356.0, 1422.0, 1491.0, 1557.0, 1617.0,
+];
+const ALLPASSES: [f32; 4] = [556.0, 441.0, 341.0, 225.0];
+const TUNING_RATE: f32 = 44100.0;
+/// How much longer the right side's delays are, so the sides don't match.
+const SPREAD: f32 = 23.0;
+const INPUT_GAIN: f32 = 0.015;
+const WET_GAIN: f32 = 3.0;
+
+const PRE_DELAY: Range = Range::new(0.0, 0.5, 0.02, Unit::Seconds, Curve::Exponential);
+
+/// A feedback comb with a low-pass in the loop, so high frequencies die away first.
+struct Comb {
+    line: DelayLine,
+    filter: f32,
+}
+
+impl Comb {
+    fn process(&mut self, input: f32, delay: usize, feedback: f32, damping: f32) -> f32 {
+        let output = self.line.tap(delay);
+        self.filter = flush_denormal(output * (1.0 - damping) + self.filter * damping);
+        self.line.push(input + self.filter * feedback);
+        output
+    }
+}
+
+/// One side of the reverb: parallel combs into a chain of all-passes.
+struct Side {
+    combs: [Comb; 8],
+    allpasses: [Allpass; 4],
+    offset: f32,
+}
+
+impl Side {
+    fn new(offset: f32) -> Side {
+        let length = |tuned: f32| ((tuned + offset) * MAX_SAMPLE_RATE / TUNING_RATE) as usize + 4;
+        Side {
+            combs: COMBS.map(|tuned| Comb {
+                line: DelayLine::new(length(tuned)),
+                filter: 0.0,
+            }),
+            allpasses: ALLPASSES.map(|tuned| Allpass::new(length(tuned))),
+            offset,
+        }
+    }
+
+    fn process(&mut self, input: f32, scale: f32, feedback: f32, damping: f32) -> f32 {
+        let mut output = 0.0;
+        for (comb, tuned) in self.combs.iter_mut().zip(COMBS) {
+            let delay = ((tuned + self.offset) * scale) as usize;
+            output += comb.process(input, delay, feedback, damping);
+        }
+        for (allpass, tuned) in self.allpasses.iter_mut().zip(ALLPASSES) {
+            output = allpass.process(output, (tuned + self.offset) * scale, 0.5);
+        }
+        output
+    }
+
+    fn clear(&mut self) {
+        for comb in &mut self.combs {
+            comb.line.clear();
+            comb.filter = 0.0;
+        }
+        for allpass in &mut self.allpasses {
+            allpass.clear();
+        }
+    }
+}
+
+/// A Freeverb-style room. Every buffer is allocated when the node is built, on the UI
+/// thread. `output` is the two sides mixed down, for the mono output.
+pub struct Reverb {
+    pre_delay: DelayLine,
+    sides: [Side; 2],
+}
+
+impl Default for Reverb {
+    fn default() -> Reverb {
+        Reverb {
+            pre_delay: DelayLine::with_seconds(PRE_DELAY.max),
+            sides: [Side::new(0.0), Side::new(SPREAD)],
+        }
+    }
+}
+
+impl NodeProcessor for Reverb {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("input"),
+            Port::new("size", PortKind::Control).with_range(LEVEL.with_default(0.5)),
+            Port::new("damping", PortKind::Control).with_range(LEVEL.with_default(0.5)),
+            Port::new("pre-delay", PortKind::Control).with_range(PRE_DELAY),
+            Port::new("mix", PortKind::Control).with_range(LEVEL.with_default(0.3)),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("output"),
+            Port::audio("left"),
+            Port::audio("right"),
+        ])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, size, damping, pre_delay, mix] = inputs.split();
+        let (input, size, damping) = (io.input(input), io.input(size), io.input(damping));
+        let (pre_delay, mix) = (io.input(pre_delay), io.input(mix));
+        let sample_rate = io.sample_rate();
+        let scale = sample_rate.min(MAX_SAMPLE_RATE) / TUNING_RATE;
+        let [output, left, right] = io.outputs();
+        let [left_side, right_side] = &mut self.sides;
+        for i in 0..output.len() {
+            let x = input.get(i);
+            let delayed = if pre_delay.get(i) * sample_rate < DelayLine::MIN_DELAY {
+                x
+            } else {
+                self.pre_delay.read(pre_delay.get(i) * sample_rate)
+            };
+            self.pre_delay.push(x);
+            let feedback = 0.7 + 0.28 * size.get(i).clamp(0.0, 1.0);
+            let damping = 0.4 * damping.get(i).clamp(0.0, 1.0);
+            let wet_input = delayed * INPUT_GAIN;
+            let wet_left = left_side.process(wet_input, scale, feedback, damping) * WET_GAIN;
+            let wet_right = right_side.process(wet_input, scale, feedback, damping) * WET_GAIN;
+            let mix = mix.get(i);
+            let dry = x * (1.0 - mix);
+            left[i] = dry + wet_left * mix;
+            right[i] = dry + wet_right * mix;
+            output[i] = dry + (wet_left + wet_right) * 0.5 * mix;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pre_delay.clear();
+        for side in &mut self.sides {
+            side.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    /// The wet outputs for `signal`, with `size` set.
+    fn reverberate(signal: &[f32], size: f32) -> Vec<Vec<f32>> {
+        let mut reverb = Reverb::default();
+        let mut inputs = reverb.inputs();
+        for (port, value) in [("size", size), ("mix", 1.0)] {
+            let port = inputs.named_mut(port).unwrap();
+            *port = port.with_value(value);
+        }
+        process_signal(&mut reverb, &mut inputs, signal, SAMPLE_RATE)
+    }
+
+    fn energy(signal: &[f32]) -> f32 {
+        signal.iter().map(|x| x * x).sum()
+    }
+
+    #[test]
+    fn tail_decays() {
+        let mut impulse = vec![0.0; 4 * SAMPLE_RATE];
+        impulse[0] = 1.0;
+        let half = SAMPLE_RATE / 2;
+        for output in reverberate(&impulse, 0.5) {
+            assert!(output.iter().all(|x| x.is_finite()));
+            let (start, end) = (
+                energy(&output[..half]),
+                energy(&output[output.len() - half..]),
+            );
+            assert!(start > 0.0);
+            // 60 dB down.
+            assert!(end < start * 1e-6, "{} then {}", start, end);
+        }
+    }
+
+    #[test]
+    fn largest_room_stays_finite() {
+        // A full-scale square, to pile up as much energy as possible.
+        let square: Vec<f32> = (0..2 * SAMPLE_RATE)
+            .map(|n| if n / 100 % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        for output in reverberate(&square, 1.0) {
+            assert!(output.iter().all(|x| x.is_finite() && x.abs() < 100.0));
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use super::*;
+
+/// MIDI note numbers, for the note a sample was recorded at. The default is A4, to match
+/// `frequency`'s 440 Hz, so an unpatched sampler plays at the recorded pitch.
+const ROOT: Range = Range::new(0.0, 127.0, 69.0, Unit::None, Curve::Linear);
+/// 0 plays at the recorded pitch whatever `frequency` is, 1 follows it.
+const TRACKING: Range = Range::new(0.0, 1.0, 1.0, Unit::None, Curve::Linear);
+/// 0 plays once, 1 loops between the loop points while the gate is held.
+const LOOP: Range = Range::new(0.0, 1.0, 0.0, Unit::None, Curve::Linear);
+
+/// Plays a loaded file from `start` whenever the gate rises. One-shots run to the end
+/// whatever the gate does. Looped, playback goes round between the loop points for as long
+/// as the gate is held, then carries on past the loop end to finish the sample. Positions
+/// are fractions of the file's length.
+#[derive(Default)]
+pub struct Sampler {
+    audio: Option<SharedAudio>,
+    /// In frames of the file.
+    position: f64,
+    playing: bool,
+    gate: bool,
+}
+
+impl NodeProcessor for Sampler {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("gate").with_range(Range::UNIPOLAR),
+            Port::audio("frequency").with_range(Range::FREQUENCY),
+            Port::new("root", PortKind::Constant).with_range(ROOT),
+            Port::new("tracking", PortKind::Constant).with_range(TRACKING),
+            Port::audio("start").with_range(Range::UNIPOLAR),
+            Port::new("loop", PortKind::Constant).with_range(LOOP),
+            Port::new("loop start", PortKind::Control).with_range(Range::UNIPOLAR),
+            Port::new("loop end", PortKind::Control).with_range(LEVEL.with_default(1.0)),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [gate, frequency, root, tracking, start, looped, loop_start, loop_end] = inputs.split();
+        let (gate, frequency, root) = (io.input(gate), io.input(frequency), io.input(root));
+        let (tracking, start, looped) = (io.input(tracking), io.input(start), io.input(looped));
+        let (loop_start, loop_end) = (io.input(loop_start), io.input(loop_end));
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        let audio = match &self.audio {
+            Some(audio) if !audio.frames.is_empty() => audio,
+            _ => {
+                output.fill(0.0);
+                return;
+            }
+        };
+        let frames = &audio.frames;
+        let length = frames.len() as f64;
+        let root = 440.0 * ((root.get(0) - 69.0) / 12.0).exp2();
+        let looped = looped.get(0) >= 0.5;
+        for (i, output) in output.iter_mut().enumerate() {
+            let gate_on = gate.get(i) > 0.0;
+            if gate_on && !self.gate {
+                self.position = start.get(i).clamp(0.0, 1.0) as f64 * length;
+                self.playing = true;
+            }
+            self.gate = gate_on;
+            if !self.playing {
+                *output = 0.0;
+                continue;
+            }
+
+            let whole = self.position as usize;
+            let fraction = (self.position - whole as f64) as f32;
+            let here = frames[whole.min(frames.len() - 1)];
+            let next = frames.get(whole + 1).copied().unwrap_or(0.0);
+            *output = here + (next - here) * fraction;
+
+            let pitch = if tracking.get(i) >= 0.5 {
+                frequency.get(i) / root
+            } else {
+                1.0
+            };
+            self.position += (pitch * audio.sample_rate / sample_rate).max(0.0) as f64;
+            let loop_start = loop_start.get(i).clamp(0.0, 1.0) as f64 * length;
+            let loop_end = loop_end.get(i).clamp(0.0, 1.0) as f64 * length;
+            if looped && self.gate && loop_end > loop_start + 1.0 && self.position >= loop_end {
+                self.position = loop_start + (self.position - loop_end) % (loop_end - loop_start);
+            } else if self.position >= length {
+                self.playing = false;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.position = 0.0;
+        self.playing = false;
+        self.gate = false;
+    }
+
+    fn save(&self) -> Vec<f32> {
+        vec![
+            self.position as f32,
+            self.playing as u8 as f32,
+            self.gate as u8 as f32,
+        ]
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let [position, playing, gate] = *state {
+            self.position = position as f64;
+            self.playing = playing != 0.0;
+            self.gate = gate != 0.0;
+        }
+    }
+
+    fn uses_audio(&self) -> bool {
+        true
+    }
+
+    fn set_audio(&mut self, audio: SharedAudio) {
+        self.position = self.position.min(audio.frames.len() as f64);
+        self.audio = Some(audio);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+    const FRAMES: usize = 100;
+
+    /// A sampler holding a ramp, so each output sample is the position it was read from.
+    fn ramp(collector: &Collector) -> Sampler {
+        let file = AudioFile {
+            name: "ramp".to_owned(),
+            sample_rate: SAMPLE_RATE as f32,
+            frames: (0..FRAMES).map(|i| i as f32).collect(),
+            prepared: None,
+        };
+        let mut sampler = Sampler::default();
+        sampler.set_audio(SharedAudio::new(&collector.handle(), file));
+        sampler
+    }
+
+    /// Plays the ramp with `gate`, and the named inputs set.
+    fn play(gate: &[f32], values: &[(&str, f32)]) -> Vec<f32> {
+        let collector = Collector::new();
+        let mut sampler = ramp(&collector);
+        let mut inputs = sampler.inputs();
+        for &(port, value) in values {
+            let port = inputs.named_mut(port).unwrap();
+            *port = port.with_value(value);
+        }
+        process_signal(&mut sampler, &mut inputs, gate, SAMPLE_RATE).remove(0)
+    }
+
+    /// The gate held for `held` samples out of `length`.
+    fn gate(held: usize, length: usize) -> Vec<f32> {
+        (0..length).map(|n| (n < held) as u8 as f32).collect()
+    }
+
+    #[test]
+    fn plays_at_the_frequency_relative_to_the_root() {
+        // An octave above A3.
+        let output = play(&gate(40, 40), &[("root", 57.0), ("frequency", 440.0)]);
+        assert!((0..40).all(|n| output[n] == 2.0 * n as f32), "{:?}", output);
+        // Without tracking, it plays at the recorded pitch.
+        let values = [("root", 57.0), ("frequency", 440.0), ("tracking", 0.0)];
+        let output = play(&gate(40, 40), &values);
+        assert!((0..40).all(|n| output[n] == n as f32), "{:?}", output);
+    }
+
+    #[test]
+    fn loops_between_the_loop_points_while_held() {
+        let values = [("loop", 1.0), ("loop start", 0.25), ("loop end", 0.5)];
+        let output = play(&gate(200, 400), &values);
+        assert!((0..50).all(|n| output[n] == n as f32));
+        // Round from 25 to 49 while the gate is held.
+        assert!((50..200).all(|n| output[n] == (25 + (n - 50) % 25) as f32));
+        // Then on to the end from wherever it was.
+        assert!((200..275).all(|n| output[n] == (n - 175) as f32));
+        assert!(output[275..].iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn one_shot_plays_to_the_end() {
+        for held in [10, 400] {
+            let output = play(&gate(held, 400), &[]);
+            assert!((0..FRAMES).all(|n| output[n] == n as f32));
+            assert!(output[FRAMES..].iter().all(|&x| x == 0.0), "held {}", held);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Nonlinearities, which add harmonics by bending the signal.
+
+use super::*;
+use crate::audio::engine::dsp::Oversampler;
+
+const DRIVE: Range = Range::new(1.0, 20.0, 1.0, Unit::Ratio, Curve::Exponential);
+/// 0 runs at the sample rate, 1 at twice it and 2 at four times.
+const OVERSAMPLING: Range = Range::new(
+    0.0,
+    Oversampler::MAX_DOUBLINGS as f32,
+    0.0,
+    Unit::None,
+    Curve::Linear,
+);
+const MODE: Range = Range::new(0.0, 2.0, 0.0, Unit::None, Curve::Linear);
+
+fn drive_and_oversampling() -> [Port; 2] {
+    [
+        Port::new("drive", PortKind::Control).with_range(DRIVE),
+        Port::new("oversampling", PortKind::Constant).with_range(OVERSAMPLING),
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Soft,
+    Hard,
+    Fold,
+}
+
+impl Mode {
+    fn from_value(value: f32) -> Mode {
+        match value.round() as i32 {
+            i32::MIN..=0 => Mode::Soft,
+            1 => Mode::Hard,
+            _ => Mode::Fold,
+        }
+    }
+
+    fn shape(self, x: f32) -> f32 {
+        match self {
+            Mode::Soft => x.tanh(),
+            Mode::Hard => x.clamp(-1.0, 1.0),
+            // Reflects off 1 and -1 for as long as it takes to end up between them.
+            Mode::Fold => 1.0 - ((x + 1.0).rem_euclid(4.0) - 2.0).abs(),
+        }
+    }
+}
+
+/// Soft clipping with tanh, hard clipping, or folding back from the rails, after `drive`.
+#[derive(Default)]
+pub struct Clip {
+    oversampler: Oversampler,
+}
+
+impl NodeProcessor for Clip {
+    fn inputs(&self) -> Ports {
+        let [drive, oversampling] = drive_and_oversampling();
+        Ports::from([
+            Port::audio("input"),
+            drive,
+            Port::new("mode", PortKind::Constant).with_range(MODE),
+            oversampling,
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, drive, mode, oversampling] = inputs.split();
+        let (input, drive, mode) = (io.input(input), io.input(drive), io.input(mode));
+        let doublings = io.input(oversampling).get(0).round().max(0.0) as usize;
+        let mode = Mode::from_value(mode.get(0));
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let drive = drive.get(i);
+            let mut shape = |x: f32| mode.shape(x * drive);
+            *output = self
+                .oversampler
+                .process(input.get(i), doublings, &mut shape);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.oversampler.clear();
+    }
+}
+
+const POINTS: usize = 9;
+const POINT_NAMES: [&str; POINTS] = [
+    "point 1", "point 2", "point 3", "point 4", "point 5", "point 6", "point 7", "point 8",
+    "point 9",
+];
+
+/// Looks the driven input up in a curve through nine points, spread evenly from -1 to 1 and
+/// joined with straight lines. Anything past either end takes the end point. The points
+/// start on a straight line, which leaves the signal alone.
+#[derive(Default)]
+pub struct Waveshaper {
+    oversampler: Oversampler,
+}
+
+impl NodeProcessor for Waveshaper {
+    fn inputs(&self) -> Ports {
+        let [drive, oversampling] = drive_and_oversampling();
+        let mut ports = Ports::from([Port::audio("input"), drive, oversampling]);
+        for (k, name) in POINT_NAMES.into_iter().enumerate() {
+            let identity = -1.0 + 2.0 * k as f32 / (POINTS - 1) as f32;
+            let range = Range::BIPOLAR.with_default(identity);
+            ports.push(Port::new(name, PortKind::Control).with_range(range));
+        }
+        ports
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, drive, oversampling, points @ ..] = inputs.split::<{ 3 + POINTS }>();
+        let (input, drive) = (io.input(input), io.input(drive));
+        let doublings = io.input(oversampling).get(0).round().max(0.0) as usize;
+        let points = points.map(|point| io.input(point));
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            let drive = drive.get(i);
+            let mut shape = |x: f32| {
+                let position = ((x * drive).clamp(-1.0, 1.0) + 1.0) * 0.5 * (POINTS - 1) as f32;
+                let below = (position as usize).min(POINTS - 2);
+                let fraction = position - below as f32;
+                let (from, to) = (points[below].get(i), points[below + 1].get(i));
+                from + (to - from) * fraction
+            };
+            *output = self
+                .oversampler
+                .process(input.get(i), doublings, &mut shape);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.oversampler.clear();
+    }
+}
+
+const BITS: Range = Range::new(1.0, 16.0, 8.0, Unit::None, Curve::Linear);
+const DOWNSAMPLE: Range = Range::new(1.0, 64.0, 1.0, Unit::Ratio, Curve::Logarithmic);
+
+/// Rounds the signal to `bits` of resolution, and holds each sample for `downsample`
+/// samples. The aliasing is the point here, so there's no oversampling.
+#[derive(Default)]
+pub struct Bitcrusher {
+    held: f32,
+    /// Samples since the held one was taken, in units of `downsample`.
+    phase: f32,
+}
+
+impl NodeProcessor for Bitcrusher {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("input"),
+            Port::new("bits", PortKind::Control).with_range(BITS),
+            Port::new("downsample", PortKind::Control).with_range(DOWNSAMPLE),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [input, bits, downsample] = inputs.split();
+        let (input, bits, downsample) = (io.input(input), io.input(bits), io.input(downsample));
+        let [output] = io.outputs();
+        for (i, output) in output.iter_mut().enumerate() {
+            self.phase += 1.0 / downsample.get(i).max(1.0);
+            if self.phase >= 1.0 {
+                self.phase -= 1.0;
+                let steps = (bits.get(i).clamp(BITS.min, BITS.max) - 1.0).exp2();
+                self.held = (input.get(i) * steps).round() / steps;
+            }
+            *output = self.held;
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Bitcrusher::default();
+    }
+
+    fn save(&self) -> Vec<f32> {
+        vec![self.held, self.phase]
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let [held, phase] = *state {
+            self.held = held;
+            self.phase = phase;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+    const LENGTH: usize = 4096;
+    /// About 4.7 kHz, so the clipped harmonics fold back between the real ones.
+    const FUNDAMENTAL_BIN: usize = 400;
+
+    /// A hard-clipped sine, skipping the first pass so the filters have settled.
+    fn clip(doublings: usize) -> Vec<f32> {
+        let signal: Vec<f32> = (0..2 * LENGTH)
+            .map(|n| {
+                let phase = (n * FUNDAMENTAL_BIN % LENGTH) as f32 / LENGTH as f32;
+                (phase * std::f32::consts::TAU).sin()
+            })
+            .collect();
+        let mut clip = Clip::default();
+        let mut inputs = clip.inputs();
+        for (port, value) in [
+            ("drive", 4.0),
+            ("mode", 1.0),
+            ("oversampling", doublings as f32),
+        ] {
+            let port = inputs.named_mut(port).unwrap();
+            *port = port.with_value(value);
+        }
+        let output = process_signal(&mut clip, &mut inputs, &signal, SAMPLE_RATE).remove(0);
+        output[LENGTH..].to_vec()
+    }
+
+    #[test]
+    fn oversampling_aliases_less() {
+        let plain = aliasing(&clip(0), FUNDAMENTAL_BIN);
+        let oversampled = aliasing(&clip(Oversampler::MAX_DOUBLINGS), FUNDAMENTAL_BIN);
+        assert!(
+            oversampled < plain - 20.0,
+            "plain {:.1} dB, oversampled {:.1} dB",
+            plain,
+            oversampled
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use super::*;
+use crate::audio::engine::dsp::fft;
+
+/// Samples per frame. Files that are a whole number of frames long are split into frames;
+/// anything else is taken as a single cycle and resampled to this length.
+const FRAME: usize = 2048;
+/// Each level has half the harmonics of the one before, down to just the fundamental.
+const LEVELS: usize = 11;
+
+/// Every frame of a file, band-limited at each level.
+struct Wavetable {
+    frames: usize,
+    /// Frame by frame, level by level.
+    tables: Vec<f32>,
+}
+
+impl Wavetable {
+    fn new(samples: &[f32]) -> Wavetable {
+        let whole_frames = samples.chunks_exact(FRAME).remainder().is_empty();
+        let cycles: Vec<Vec<f32>> = if !samples.is_empty() && whole_frames {
+            samples.chunks(FRAME).map(<[f32]>::to_vec).collect()
+        } else {
+            let step = samples.len() as f32 / FRAME as f32;
+            let cycle = (0..FRAME)
+                .map(|i| {
+                    let position = i as f32 * step;
+                    let (whole, fraction) = (position as usize, position.fract());
+                    let here = samples.get(whole).copied().unwrap_or(0.0);
+                    let next = samples.get(whole + 1).copied().unwrap_or(here);
+                    here + (next - here) * fraction
+                })
+                .collect();
+            vec![cycle]
+        };
+        let mut tables = Vec::with_capacity(cycles.len() * LEVELS * FRAME);
+        for cycle in &cycles {
+            let (mut spectrum_re, mut spectrum_im) = (cycle.clone(), vec![0.0; FRAME]);
+            fft(&mut spectrum_re, &mut spectrum_im, false);
+            for level in 0..LEVELS {
+                let harmonics = (FRAME / 2) >> level;
+                let (mut re, mut im) = (spectrum_re.clone(), spectrum_im.clone());
+                // Clear everything above the harmonic limit, on both sides of the spectrum.
+                for bin in harmonics + 1..FRAME - harmonics {
+                    re[bin] = 0.0;
+                    im[bin] = 0.0;
+                }
+                fft(&mut re, &mut im, true);
+                tables.extend(re.iter().map(|sample| sample / FRAME as f32));
+            }
+        }
+        Wavetable {
+            frames: cycles.len(),
+            tables,
+        }
+    }
+
+    fn table(&self, frame: usize, level: usize) -> &[f32] {
+        let start = (frame * LEVELS + level) * FRAME;
+        &self.tables[start..start + FRAME]
+    }
+
+    /// The sample at `phase` in `frame`, at a level with no harmonics above Nyquist for
+    /// this `increment`.
+    fn read(&self, frame: usize, phase: f32, increment: f32) -> f32 {
+        let harmonics = 0.5 / increment.max(f32::EPSILON);
+        let level = ((FRAME / 2) as f32 / harmonics).log2().ceil().max(0.0) as usize;
+        let table = self.table(frame, level.min(LEVELS - 1));
+        let position = phase * FRAME as f32;
+        let whole = position as usize % FRAME;
+        let fraction = position.fract();
+        let (here, next) = (table[whole], table[(whole + 1) % FRAME]);
+        here + (next - here) * fraction
+    }
+}
+
+/// Plays a wavetable loaded from a file, morphing between its frames with `position`.
+#[derive(Default)]
+pub struct WavetableOscillator {
+    audio: Option<SharedAudio>,
+    phase: f32,
+}
+
+impl NodeProcessor for WavetableOscillator {
+    fn inputs(&self) -> Ports {
+        Ports::from([
+            Port::audio("frequency").with_range(Range::FREQUENCY),
+            Port::audio("position").with_range(Range::UNIPOLAR),
+        ])
+    }
+
+    fn outputs(&self) -> Ports {
+        Ports::from([Port::audio("output")])
+    }
+
+    fn process(&mut self, inputs: &mut Ports, io: &mut NodeIo) {
+        let [frequency, position] = inputs.split();
+        let (frequency, position) = (io.input(frequency), io.input(position));
+        let sample_rate = io.sample_rate();
+        let [output] = io.outputs();
+        let wavetable = self
+            .audio
+            .as_ref()
+            .and_then(|audio| audio.prepared.as_ref())
+            .and_then(|prepared| prepared.downcast_ref::<Wavetable>());
+        let wavetable = match wavetable {
+            Some(wavetable) => wavetable,
+            None => {
+                output.fill(0.0);
+                return;
+            }
+        };
+        let last = (wavetable.frames - 1) as f32;
+        for (i, output) in output.iter_mut().enumerate() {
+            // A NaN would stick in the phase, as it does in `Oscillator`.
+            let increment = frequency.get(i) / sample_rate;
+            let increment = if increment.is_finite() {
+                increment.clamp(0.0, 0.5)
+            } else {
+                0.0
+            };
+            let frame = position.get(i).clamp(0.0, 1.0) * last;
+            let (below, fraction) = (frame as usize, frame.fract());
+            let here = wavetable.read(below, self.phase, increment);
+            *output = if fraction > 0.0 {
+                let next = wavetable.read(below + 1, self.phase, increment);
+                here + (next - here) * fraction
+            } else {
+                here
+            };
+            self.phase = (self.phase + increment).fract();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    fn save(&self) -> Vec<f32> {
+        vec![self.phase]
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if let [phase] = *state {
+            self.phase = phase;
+        }
+    }
+
+    fn uses_audio(&self) -> bool {
+        true
+    }
+
+    fn set_audio(&mut self, audio: SharedAudio) {
+        self.audio = Some(audio);
+    }
+
+    fn prepare_audio(file: &mut AudioFile) {
+        file.prepared = Some(Box::new(Wavetable::new(&file.frames)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+    const LENGTH: usize = 4096;
+    /// About 3.5 kHz, where a full saw would have harmonics well above Nyquist.
+    const FUNDAMENTAL_BIN: usize = 300;
+
+    /// An oscillator playing a single-frame saw.
+    fn saw(collector: &Collector) -> WavetableOscillator {
+        let saw = (0..FRAME)
+            .map(|i| 2.0 * i as f32 / FRAME as f32 - 1.0)
+            .collect();
+        let mut file = AudioFile {
+            name: "saw".to_owned(),
+            sample_rate: SAMPLE_RATE as f32,
+            frames: saw,
+            prepared: None,
+        };
+        WavetableOscillator::prepare_audio(&mut file);
+        let mut oscillator = WavetableOscillator::default();
+        oscillator.set_audio(SharedAudio::new(&collector.handle(), file));
+        oscillator
+    }
+
+    #[test]
+    fn high_notes_are_band_limited() {
+        let collector = Collector::new();
+        let mut oscillator = saw(&collector);
+        let mut inputs = oscillator.inputs();
+        let frequency = (FUNDAMENTAL_BIN * SAMPLE_RATE) as f32 / LENGTH as f32;
+        inputs[0] = inputs[0].with_value(frequency);
+        let output = render_processor(&mut oscillator, &mut inputs, LENGTH, SAMPLE_RATE);
+
+        let naive: Vec<f32> = (0..LENGTH)
+            .map(|n| 2.0 * (n * FUNDAMENTAL_BIN % LENGTH) as f32 / LENGTH as f32 - 1.0)
+            .collect();
+        let naive = aliasing(&naive, FUNDAMENTAL_BIN);
+        let band_limited = aliasing(&output[0], FUNDAMENTAL_BIN);
+        assert!(
+            band_limited < -60.0 && band_limited < naive - 40.0,
+            "naive {:.1} dB, band-limited {:.1} dB",
+            naive,
+            band_limited
+        );
+    }
+
+    #[test]
+    fn non_finite_frequency_holds_the_phase() {
+        let collector = Collector::new();
+        let mut oscillator = saw(&collector);
+        let mut inputs = oscillator.inputs();
+        for frequency in [f32::NAN, f32::INFINITY] {
+            inputs[0] = inputs[0].with_value(frequency);
+            let output = render_processor(&mut oscillator, &mut inputs, 64, SAMPLE_RATE).remove(0);
+            assert!(output.iter().all(|sample| sample.is_finite()));
+            assert!(oscillator.phase.is_finite());
+        }
+    }
+}
@@ -2,9 +2,17 @@ use arrayvec::ArrayVec;
 
 use super::*;
 
-pub type Audio = [f32; 256];
+pub type Audio = Box<[f32]>;
 pub type Control = f32;
-pub type NodeList = Owned<ArrayVec<Node, 16>>;
+pub const MAX_NODES: usize = 16;
+/// Each node is owned separately, so that one the engine turns down isn't freed on the audio
+/// thread either.
+pub type NodeList = Owned<ArrayVec<Owned<Node>, MAX_NODES>>;
+/// Block size used until the JACK period is known.
+pub const DEFAULT_BLOCK_SIZE: usize = 256;
+/// Slots are allocated this long up front, so changing the block size never allocates.
+/// Larger buffers are processed this many samples at a time.
+pub const MAX_BLOCK_SIZE: usize = 1024;
 
 pub struct Stack {
     pub nodes: NodeList,
@@ -15,31 +23,173 @@ pub struct Stack {
 pub struct StackData {
     pub audio: Vec<Audio>,
     pub control: Vec<Control>,
+    /// Where a node writes its outputs before they're swapped into `audio`.
+    pub scratch: Vec<Audio>,
+    /// How much of each slot is in use.
+    block_size: usize,
 }
 
 impl Stack {
-    pub fn new(nodes: NodeList) -> Stack {
+    pub fn new(nodes: NodeList, block_size: usize) -> Stack {
         Stack {
             nodes,
-            data: StackData::default(),
+            data: StackData::new(block_size),
         }
     }
 
+    /// Mixes the stack's output into `output_buffer`, a block at a time. The buffer doesn't
+    /// need to be a multiple of the block size.
     pub fn process(&mut self, output_buffer: &mut [f32], sample_rate: usize) {
-        for chunk in output_buffer.chunks_mut(256) {
+        for chunk in output_buffer.chunks_mut(self.data.block_size()) {
             for node in &mut *self.nodes {
                 node.process(chunk.len(), &mut self.data, sample_rate);
             }
-            chunk.copy_from_slice(&self.data.audio[0][..chunk.len()]);
+            for (output, sample) in chunk.iter_mut().zip(&*self.data.audio[0]) {
+                *output += sample;
+            }
+        }
+    }
+
+    /// Clears the buffers and every node's state.
+    pub fn reset(&mut self) {
+        self.data.clear();
+        for node in &mut *self.nodes {
+            node.reset();
         }
     }
+
+    /// Only changes how much of each slot is used, so it's fine to call on the audio thread.
+    pub fn set_block_size(&mut self, block_size: usize) {
+        self.data.block_size = block_size.clamp(1, MAX_BLOCK_SIZE);
+    }
 }
 
-impl Default for StackData {
-    fn default() -> Self {
+impl StackData {
+    pub fn new(block_size: usize) -> StackData {
         StackData {
-            audio: vec![[0.0; 256]; 256],
+            audio: StackData::audio_slots(256),
             control: vec![0.0; 256],
+            scratch: StackData::audio_slots(MAX_OUTPUTS),
+            block_size: block_size.clamp(1, MAX_BLOCK_SIZE),
         }
     }
+
+    fn audio_slots(count: usize) -> Vec<Audio> {
+        vec![vec![0.0; MAX_BLOCK_SIZE].into_boxed_slice(); count]
+    }
+
+    /// Moves a node's freshly written outputs from scratch into the slots they're connected
+    /// to. Audio buffers are swapped rather than copied; control slots take the last sample.
+    pub fn commit<'a>(&mut self, outputs: impl Iterator<Item = &'a Port>, samples: usize) {
+        for (scratch, port) in self.scratch.iter_mut().zip(outputs) {
+            match port.slot {
+                Some(Slot::Audio(index)) => {
+                    std::mem::swap(&mut self.audio[index as usize], scratch);
+                }
+                Some(Slot::Control(index)) if samples > 0 => {
+                    self.control[index as usize] = scratch[samples - 1];
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Zeroes every slot without reallocating, so it's fine to call on the audio thread.
+    pub fn clear(&mut self) {
+        for audio in &mut self.audio {
+            audio.fill(0.0);
+        }
+        self.control.fill(0.0);
+    }
+}
+
+impl Default for StackData {
+    fn default() -> Self {
+        StackData::new(DEFAULT_BLOCK_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+    const LENGTH: usize = 4096;
+
+    /// An oscillator through `Abs` into the output slot.
+    fn stack(collector: &Collector, block_size: usize) -> Stack {
+        let handle = collector.handle();
+        let mut oscillator = Node::new(NodeKind::named("Oscillator").unwrap());
+        let frequency = oscillator.inputs.named_mut("frequency").unwrap();
+        *frequency = frequency.with_value(441.0);
+        oscillator.outputs[0].slot = Some(Slot::Audio(1));
+        let mut abs = Node::new(NodeKind::named("Abs").unwrap());
+        abs.inputs[0].slot = Some(Slot::Audio(1));
+        abs.outputs[0].slot = Some(Slot::Audio(0));
+        let nodes = [oscillator, abs]
+            .into_iter()
+            .map(|node| Owned::new(&handle, node))
+            .collect();
+        Stack::new(Owned::new(&handle, nodes), block_size)
+    }
+
+    fn render(block_size: usize, buffer_size: usize) -> Vec<f32> {
+        let collector = Collector::new();
+        let mut stack = stack(&collector, block_size);
+        let mut output = vec![0.0; LENGTH];
+        for buffer in output.chunks_mut(buffer_size) {
+            stack.process(buffer, SAMPLE_RATE);
+        }
+        output
+    }
+
+    #[test]
+    fn block_sizes_agree() {
+        let reference = render(1, 1);
+        assert!(reference.iter().any(|&sample| sample != 0.0));
+        for block_size in [64, 256, 300, 512, 1024, 2048] {
+            assert_eq!(
+                render(block_size, block_size),
+                reference,
+                "block size {}",
+                block_size
+            );
+        }
+    }
+
+    #[test]
+    fn partial_blocks_agree() {
+        let reference = render(256, 256);
+        for (block_size, buffer_size) in [(256, 100), (300, 1024), (2048, 1000), (512, 2048)] {
+            assert_eq!(
+                render(block_size, buffer_size),
+                reference,
+                "block size {}, buffer size {}",
+                block_size,
+                buffer_size
+            );
+        }
+    }
+
+    #[test]
+    fn block_size_changes_midway() {
+        let reference = render(256, 256);
+        let collector = Collector::new();
+        let mut stack = stack(&collector, 256);
+        let mut output = vec![0.0; LENGTH];
+        let (first, second) = output.split_at_mut(LENGTH / 2);
+        stack.process(first, SAMPLE_RATE);
+        let slots = stack.data.audio.as_ptr();
+        stack.set_block_size(1024);
+        // Nothing was reallocated.
+        assert_eq!(stack.data.audio.as_ptr(), slots);
+        stack.process(second, SAMPLE_RATE);
+        assert_eq!(output, reference);
+    }
 }
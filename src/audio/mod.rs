@@ -1,4 +1,4 @@
-use std::convert::TryFrom;
+use std::{convert::TryFrom, sync::Mutex};
 
 pub use self::error::Error;
 
@@ -7,23 +7,163 @@ mod engine;
 pub use engine::*;
 mod error;
 
+const MIDI_IN_NAME: &str = "capture_1";
+const AUDIO_OUT_NAME: &str = "playback_1";
+/// How many commands can be waiting for the next process cycle.
+const COMMAND_QUEUE_SIZE: usize = 64;
+
 pub struct Controller {
     pub active_client: jack::AsyncClient<NotificationHandler, ProcessHandler>,
-    pub midi_ui: rtrb::Consumer<wmidi::MidiMessage<'static>>,
+    pub feedback: rtrb::Consumer<Feedback>,
+    pub notifications: rtrb::Consumer<Notification>,
+    /// Notifications from the process thread, which gets a queue of its own so the two
+    /// threads never share a producer.
+    pub process_notifications: rtrb::Consumer<Notification>,
     pub input: rtrb::Producer<Command>,
 }
 
-pub struct NotificationHandler {}
+/// Something the engine wants the UI to know, sent from the process thread.
+#[derive(Clone, Debug)]
+pub enum Feedback {
+    MidiIn(wmidi::MidiMessage<'static>),
+    /// A command has been taken off the queue and either applied or rejected.
+    Command(CommandKind, Result<(), EngineError>),
+    /// Number of notes currently held.
+    Voices(u8),
+    /// Output peak since the previous meter reading.
+    Meter(f32),
+    /// The process callback skipped this many frames since the last cycle.
+    Xrun(jack::Frames),
+}
+
+/// Something the JACK server told us about, on its way to the UI.
+#[derive(Clone, Debug)]
+pub enum Notification {
+    Xrun,
+    SampleRate(usize),
+    BufferSize(usize),
+    /// One of our ports now has this many connections.
+    Connections(ClientPort, usize),
+    /// The server went away; the client is dead and has to be restarted.
+    Shutdown(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClientPort {
+    MidiIn,
+    AudioOut,
+}
+
+impl ClientPort {
+    fn from_short_name(name: &str) -> Option<ClientPort> {
+        match name {
+            MIDI_IN_NAME => Some(ClientPort::MidiIn),
+            AUDIO_OUT_NAME => Some(ClientPort::AudioOut),
+            _ => None,
+        }
+    }
+}
+
+/// JACK needs the notification handler to be `Sync`, so its producer sits behind a lock. Only
+/// the notification thread takes it; the process thread has a queue of its own.
+pub struct NotificationHandler {
+    notifications: Mutex<rtrb::Producer<Notification>>,
+}
+
+impl NotificationHandler {
+    fn notify(&self, notification: Notification) {
+        if let Ok(mut notifications) = self.notifications.lock() {
+            notifications.push(notification).ok();
+        }
+    }
+
+    fn port_connections(&self, client: &jack::Client, port_id: jack::PortId) {
+        let port = match client.port_by_id(port_id) {
+            Some(port) if client.is_mine(&port) => port,
+            _ => return,
+        };
+        let client_port = match port
+            .short_name()
+            .ok()
+            .as_deref()
+            .and_then(ClientPort::from_short_name)
+        {
+            Some(client_port) => client_port,
+            None => return,
+        };
+        if let Ok(count) = port.connected_count() {
+            self.notify(Notification::Connections(client_port, count));
+        }
+    }
+}
+
+impl jack::NotificationHandler for NotificationHandler {
+    fn shutdown(&mut self, _status: jack::ClientStatus, reason: &str) {
+        self.notify(Notification::Shutdown(reason.to_owned()));
+    }
+
+    fn sample_rate(&mut self, _: &jack::Client, srate: jack::Frames) -> jack::Control {
+        self.notify(Notification::SampleRate(srate as usize));
+        jack::Control::Continue
+    }
+
+    fn ports_connected(
+        &mut self,
+        client: &jack::Client,
+        port_id_a: jack::PortId,
+        port_id_b: jack::PortId,
+        _are_connected: bool,
+    ) {
+        self.port_connections(client, port_id_a);
+        self.port_connections(client, port_id_b);
+    }
 
-impl jack::NotificationHandler for NotificationHandler {}
+    fn xrun(&mut self, _: &jack::Client) -> jack::Control {
+        self.notify(Notification::Xrun);
+        jack::Control::Continue
+    }
+}
 
 pub struct ProcessHandler {
     midi_in: jack::Port<jack::MidiIn>,
     audio_out: jack::Port<jack::AudioOut>,
     engine: engine::Engine,
 
-    midi_ui: rtrb::Producer<wmidi::MidiMessage<'static>>,
+    feedback: rtrb::Producer<Feedback>,
+    /// For the buffer size callback, which JACK runs on the process thread.
+    notifications: rtrb::Producer<Notification>,
     input: rtrb::Consumer<Command>,
+
+    voices: u8,
+    peak: f32,
+    meter_frames: usize,
+    next_frame_time: Option<jack::Frames>,
+}
+
+impl ProcessHandler {
+    /// How many meter readings are sent per second.
+    const METER_RATE: usize = 20;
+
+    fn check_frame_time(&mut self, process_scope: &jack::ProcessScope) {
+        let frame_time = process_scope.last_frame_time();
+        if let Some(expected) = self.next_frame_time {
+            let missed = frame_time.wrapping_sub(expected);
+            if missed != 0 && missed < jack::Frames::MAX / 2 {
+                self.feedback.push(Feedback::Xrun(missed)).ok();
+            }
+        }
+        self.next_frame_time = Some(frame_time.wrapping_add(process_scope.n_frames()));
+    }
+
+    fn meter(&mut self, peak: f32, frames: usize) {
+        self.peak = self.peak.max(peak);
+        self.meter_frames += frames;
+        if self.meter_frames >= self.engine.sample_rate() / Self::METER_RATE {
+            self.feedback.push(Feedback::Meter(self.peak)).ok();
+            self.peak = 0.0;
+            self.meter_frames = 0;
+        }
+    }
 }
 
 impl jack::ProcessHandler for ProcessHandler {
@@ -32,29 +172,53 @@ impl jack::ProcessHandler for ProcessHandler {
         client: &jack::Client,
         process_scope: &jack::ProcessScope,
     ) -> jack::Control {
+        self.check_frame_time(process_scope);
+        if client.sample_rate() != self.engine.sample_rate() {
+            self.engine.set_sample_rate(client.sample_rate());
+        }
         while let Ok(command) = self.input.pop() {
-            self.engine.run_command(command);
+            let kind = CommandKind::from(&command);
+            let result = self.engine.run_command(command);
+            self.feedback.push(Feedback::Command(kind, result)).ok();
         }
         for data in self.midi_in.iter(process_scope) {
             use wmidi::MidiMessage;
             if let Ok(Some(midi_message)) =
                 MidiMessage::try_from(data.bytes).map(|m| m.drop_unowned_sysex())
             {
-                self.midi_ui.push(midi_message.clone()).ok();
+                self.feedback
+                    .push(Feedback::MidiIn(midi_message.clone()))
+                    .ok();
                 self.engine.midi_in(midi_message);
             }
         }
         if self.midi_in.connected_count() == Ok(0) {
             self.engine.midi_in(wmidi::MidiMessage::Reset);
         }
+        if self.engine.voices() != self.voices {
+            self.voices = self.engine.voices();
+            self.feedback.push(Feedback::Voices(self.voices)).ok();
+        }
         if self.audio_out.connected_count() == Ok(0) {
             return jack::Control::Quit;
         }
         let buffer = self.audio_out.as_mut_slice(process_scope);
-        //self.engine.stack.process(buffer, client.sample_rate());
-        for sample in buffer {
+        self.engine.process(buffer);
+        let mut peak = 0.0f32;
+        for sample in buffer.iter_mut() {
             *sample = sample.clamp(-1.0, 1.0);
+            peak = peak.max(sample.abs());
         }
+        let frames = buffer.len();
+        self.meter(peak, frames);
+        jack::Control::Continue
+    }
+
+    fn buffer_size(&mut self, _: &jack::Client, size: jack::Frames) -> jack::Control {
+        self.engine.set_block_size(size as usize);
+        self.notifications
+            .push(Notification::BufferSize(size as usize))
+            .ok();
         jack::Control::Continue
     }
 }
@@ -62,24 +226,36 @@ impl jack::ProcessHandler for ProcessHandler {
 pub fn start() -> Result<Controller, Error> {
     let (client, _status) =
         jack::Client::new("musicprogram", jack::ClientOptions::NO_START_SERVER)?;
-    let midi_in = client.register_port("capture_1", jack::MidiIn)?;
-    let audio_out = client.register_port("playback_1", jack::AudioOut)?;
-    let notification_handler = NotificationHandler {};
-    let data = rtrb::RingBuffer::new(128);
-    let input = rtrb::RingBuffer::new(1);
+    let midi_in = client.register_port(MIDI_IN_NAME, jack::MidiIn)?;
+    let audio_out = client.register_port(AUDIO_OUT_NAME, jack::AudioOut)?;
+    let notifications = rtrb::RingBuffer::new(64);
+    let process_notifications = rtrb::RingBuffer::new(16);
+    let notification_handler = NotificationHandler {
+        notifications: Mutex::new(notifications.0),
+    };
+    let feedback = rtrb::RingBuffer::new(256);
+    let input = rtrb::RingBuffer::new(COMMAND_QUEUE_SIZE);
     let process_handler = ProcessHandler {
         midi_in,
         audio_out,
-        engine: engine::Engine::new(),
+        engine: engine::Engine::new(client.sample_rate(), client.buffer_size() as usize),
 
-        midi_ui: data.0,
+        feedback: feedback.0,
+        notifications: process_notifications.0,
         input: input.1,
+
+        voices: 0,
+        peak: 0.0,
+        meter_frames: 0,
+        next_frame_time: None,
     };
 
     let active_client = client.activate_async(notification_handler, process_handler)?;
     Ok(Controller {
         active_client,
-        midi_ui: data.1,
+        feedback: feedback.1,
+        notifications: notifications.1,
+        process_notifications: process_notifications.1,
         input: input.0,
     })
 }
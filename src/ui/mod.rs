@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, rc::Rc, sync::mpsc};
 
 use vizia::*;
 
@@ -11,11 +11,29 @@ pub use model::*;
 pub fn start() {
     let window_desc = WindowDescription::new().with_title("musicprogram");
     let mut controller = audio::start().unwrap();
+    let sample_rate = controller.active_client.as_client().sample_rate();
+    let buffer_size = controller.active_client.as_client().buffer_size() as usize;
     let audio_tx = Rc::new(RefCell::new(controller.input));
-    let collector = basedrop::Collector::new();
+    let mut collector = basedrop::Collector::new();
+    let handle = collector.handle();
+    // Anything the engine lets go of is queued up here instead of being freed on the audio
+    // thread.
+    std::thread::spawn(move || loop {
+        collector.collect();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    });
+    let (load_tx, load_rx) = mpsc::channel::<LoadRequest>();
+    let loader_collector = handle.clone();
     let app = Application::new(window_desc, move |cx| {
         cx.add_stylesheet("style.css").ok();
-        model::MainModel::new(audio_tx.clone()).build(cx);
+        model::MainModel::new(
+            audio_tx.clone(),
+            handle.clone(),
+            load_tx.clone(),
+            sample_rate,
+            buffer_size,
+        )
+        .build(cx);
         ZStack::new(cx, |cx| {
             VStack::new(cx, |cx| {
                 views::node_list::build(cx);
@@ -23,6 +41,86 @@ pub fn start() {
                     Binding::new(cx, model::MainModel::note, |cx, note| {
                         Label::new(cx, note.get(cx).to_str()).class("current-note");
                     });
+                    Binding::new(cx, model::MainModel::audio_connections, |cx, count| {
+                        let status = match *count.get(cx) {
+                            0 => "Output disconnected".to_owned(),
+                            count => format!("Output connected ({})", count),
+                        };
+                        Label::new(cx, &status).class("connections");
+                    });
+                    Binding::new(cx, model::MainModel::midi_connections, |cx, count| {
+                        Label::new(cx, &format!("MIDI inputs: {}", count.get(cx)))
+                            .class("connections");
+                    });
+                    Binding::new(cx, model::MainModel::tempo, |cx, tempo| {
+                        let tempo = *tempo.get(cx);
+                        HStack::new(cx, move |cx| {
+                            Button::new(
+                                cx,
+                                move |cx| {
+                                    cx.emit(AppEvent::SetTempo(tempo - 1.0));
+                                },
+                                |cx| Label::new(cx, "-"),
+                            );
+                            Label::new(cx, &audio::Range::TEMPO.display(tempo));
+                            Button::new(
+                                cx,
+                                move |cx| {
+                                    cx.emit(AppEvent::SetTempo(tempo + 1.0));
+                                },
+                                |cx| Label::new(cx, "+"),
+                            );
+                        })
+                        .class("tempo");
+                    });
+                    Binding::new(cx, model::MainModel::voices, |cx, voices| {
+                        Label::new(cx, &format!("Voices: {}", voices.get(cx))).class("voices");
+                    });
+                    Binding::new(cx, model::MainModel::peak, |cx, peak| {
+                        let db = 20.0 * peak.get(cx).log10();
+                        let text = if db.is_finite() {
+                            format!("Peak: {:.1} dB", db)
+                        } else {
+                            "Peak: -inf dB".to_owned()
+                        };
+                        Label::new(cx, &text).class("meter");
+                    });
+                    Binding::new(cx, model::MainModel::xruns, |cx, xruns| {
+                        Label::new(cx, &format!("Xruns: {}", xruns.get(cx))).class("xruns");
+                    });
+                    Binding::new(cx, model::MainModel::dropped_frames, |cx, frames| {
+                        Label::new(cx, &format!("Dropped frames: {}", frames.get(cx)))
+                            .class("xruns");
+                    });
+                    Binding::new(cx, model::MainModel::backlog, |cx, backlog| {
+                        let backlog = *backlog.get(cx);
+                        if backlog > 0 {
+                            let text = format!("Engine is behind, {} edits waiting", backlog);
+                            Label::new(cx, &text).class("engine-error");
+                        }
+                    });
+                    Binding::new(cx, model::MainModel::engine_error, |cx, error| {
+                        if let Some(error) = error.get(cx).clone() {
+                            // It stays until the same kind of command succeeds, or it's
+                            // clicked away.
+                            Button::new(
+                                cx,
+                                |cx| cx.emit(AppEvent::DismissEngineError),
+                                move |cx| Label::new(cx, &error),
+                            )
+                            .class("engine-error");
+                        }
+                    });
+                    Binding::new(cx, model::MainModel::load_error, |cx, error| {
+                        if let Some(error) = error.get(cx) {
+                            Label::new(cx, error).class("engine-error");
+                        }
+                    });
+                    Binding::new(cx, model::MainModel::expression_error, |cx, error| {
+                        if let Some(error) = error.get(cx) {
+                            Label::new(cx, error).class("engine-error");
+                        }
+                    });
                 })
                 .class("status-bar");
             });
@@ -31,17 +129,52 @@ pub fn start() {
     });
     let proxy = app.get_proxy();
     std::thread::spawn(move || loop {
-        while let Ok(midi_message) = controller.midi_ui.pop() {
-            if let Err(_) = proxy.send_event(Event::new(AppEvent::MidiIn(midi_message))) {
+        while let Ok(feedback) = controller.feedback.pop() {
+            if proxy
+                .send_event(Event::new(AppEvent::from(feedback)))
+                .is_err()
+            {
                 return;
             }
         }
+        let notifications = [
+            &mut controller.notifications,
+            &mut controller.process_notifications,
+        ];
+        for notifications in notifications {
+            while let Ok(notification) = notifications.pop() {
+                if proxy
+                    .send_event(Event::new(AppEvent::Notification(notification)))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
         std::thread::sleep(std::time::Duration::from_millis(10));
     });
+    // Decoding a file can take a while, so it's done here rather than holding up the UI.
+    let proxy = app.get_proxy();
+    std::thread::spawn(move || {
+        for LoadRequest(id, kind, path) in load_rx {
+            let result = audio::AudioFile::load(&path)
+                .map(|mut file| {
+                    kind.prepare_audio(&mut file);
+                    audio::SharedAudio::new(&loader_collector, file)
+                })
+                .map_err(|error| format!("Couldn't load {}: {}", path.display(), error));
+            if proxy
+                .send_event(Event::new(AppEvent::AudioLoaded(id, result)))
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
     app.run();
 }
 
-impl Data for crate::audio::Node {
+impl Data for NodeInfo {
     fn same(&self, other: &Self) -> bool {
         self == other
     }
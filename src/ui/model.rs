@@ -1,9 +1,14 @@
 use std::{
     cell::RefCell,
+    collections::VecDeque,
     ops::{Deref, DerefMut},
+    path::PathBuf,
     rc::Rc,
+    sync::mpsc,
 };
 
+use arrayvec::ArrayVec;
+use basedrop::Owned;
 use vizia::*;
 use wmidi::MidiMessage;
 
@@ -11,20 +16,172 @@ use crate::audio::{self, Command};
 
 type AudioTx = Rc<RefCell<rtrb::Producer<audio::Command>>>;
 
+/// Asks the loader thread to decode a file for the node with this ID, and prepare it for
+/// that kind of node.
+#[derive(Clone, Debug)]
+pub struct LoadRequest(pub NodeId, pub audio::NodeKind, pub PathBuf);
+
+/// Identifies a node for as long as it exists. Removing a node shifts the indices of the ones
+/// after it, so anything that finishes later, like loading a file, refers to nodes by ID.
+pub type NodeId = u64;
+
+/// What the UI shows of a node. The node itself, processor and all, goes to the engine, so
+/// what it's in the middle of stays there when other nodes are added or removed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeInfo {
+    pub id: NodeId,
+    pub kind: audio::NodeKind,
+    pub inputs: audio::Ports,
+    pub outputs: audio::Ports,
+    pub uses_audio: bool,
+    pub audio: Option<audio::SharedAudio>,
+    pub uses_program: bool,
+    pub program: Option<audio::SharedProgram>,
+}
+
 #[derive(Lens)]
 pub struct MainModel {
     pub note: Note,
     pub audio_event_tx: AudioTx,
-    pub nodes: Vec<audio::Node>,
+    /// Everything handed to the engine is allocated through this, so the audio thread never
+    /// frees memory itself.
+    pub collector: basedrop::Handle,
+    pub nodes: Vec<NodeInfo>,
+    pub next_node_id: NodeId,
+    pub xruns: usize,
+    pub sample_rate: usize,
+    pub buffer_size: usize,
+    pub midi_connections: usize,
+    pub audio_connections: usize,
+    pub server_gone: Option<String>,
+    /// Commands that didn't fit in the engine's queue yet, oldest first.
+    pub pending: VecDeque<Command>,
+    /// Length of `pending`, for display.
+    pub backlog: usize,
+    pub engine_error: Option<String>,
+    /// The kind of command `engine_error` came from. Only that kind succeeding clears it.
+    pub engine_error_from: Option<audio::CommandKind>,
+    pub voices: u8,
+    pub peak: f32,
+    /// Frames the process callback missed, as measured from JACK's frame times.
+    pub dropped_frames: usize,
+    /// Beats per minute for tempo-synced nodes.
+    pub tempo: f32,
+    pub audio_loader: mpsc::Sender<LoadRequest>,
+    /// The node the file picker is choosing audio for.
+    pub choosing_audio: Option<NodeId>,
+    pub load_error: Option<String>,
+    /// Why the last expression typed into a node didn't compile.
+    pub expression_error: Option<String>,
 }
 
 impl MainModel {
-    pub fn new(audio_event_tx: AudioTx) -> Self {
-        MainModel {
+    pub fn new(
+        audio_event_tx: AudioTx,
+        collector: basedrop::Handle,
+        audio_loader: mpsc::Sender<LoadRequest>,
+        sample_rate: usize,
+        buffer_size: usize,
+    ) -> Self {
+        let mut model = MainModel {
             note: Note(wmidi::Note::LOWEST_NOTE),
             audio_event_tx,
+            collector,
             nodes: Vec::new(),
+            next_node_id: 0,
+            xruns: 0,
+            sample_rate,
+            buffer_size,
+            midi_connections: 0,
+            audio_connections: 0,
+            server_gone: None,
+            pending: VecDeque::new(),
+            backlog: 0,
+            engine_error: None,
+            engine_error_from: None,
+            voices: 0,
+            peak: 0.0,
+            dropped_frames: 0,
+            tempo: audio::Range::TEMPO.default,
+            audio_loader,
+            choosing_audio: None,
+            load_error: None,
+            expression_error: None,
+        };
+        let nodes = Owned::new(&model.collector, ArrayVec::new());
+        let stack = audio::Stack::new(nodes, model.buffer_size);
+        model.send(Command::SetChannel(0, Owned::new(&model.collector, stack)));
+        model
+    }
+
+    fn index_of(&self, id: NodeId) -> Option<usize> {
+        self.nodes.iter().position(|node| node.id == id)
+    }
+
+    /// Hands `node` to the engine, keeping what the UI needs of it.
+    fn add_node(&mut self, node: audio::Node) {
+        self.nodes.push(NodeInfo {
+            id: self.next_node_id,
+            kind: node.kind(),
+            inputs: node.inputs.clone(),
+            outputs: node.outputs.clone(),
+            uses_audio: node.uses_audio(),
+            audio: node.audio().cloned(),
+            uses_program: node.uses_program(),
+            program: node.program().cloned(),
+        });
+        self.next_node_id += 1;
+        self.send(Command::AddNode(0, Owned::new(&self.collector, node)));
+    }
+
+    fn set_engine_error(&mut self, kind: audio::CommandKind, error: String) {
+        self.engine_error = Some(error);
+        self.engine_error_from = Some(kind);
+    }
+
+    /// The first of `count` audio slots after the highest one any node is connected to, so
+    /// new connections don't disturb existing ones, or `None` if they don't fit. Slot 0 is the
+    /// output.
+    fn free_audio_slots(&self, count: usize) -> Option<u8> {
+        let used = self
+            .nodes
+            .iter()
+            .flat_map(|node| node.inputs.iter().chain(node.outputs.iter()))
+            .filter_map(|port| match port.slot {
+                Some(audio::Slot::Audio(index)) => Some(index),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        let first = used.checked_add(1)?;
+        (first as usize + count <= u8::MAX as usize + 1).then_some(first)
+    }
+
+    /// Whether any node's output goes to `slot`.
+    fn writes_to(&self, slot: audio::Slot) -> bool {
+        self.nodes
+            .iter()
+            .flat_map(|node| node.outputs.iter())
+            .any(|port| port.slot == Some(slot))
+    }
+
+    /// Queues `command` behind anything still pending, dropping pending commands it makes
+    /// redundant, and sends as much as the engine has room for.
+    fn send(&mut self, command: Command) {
+        self.pending.retain(|earlier| !command.supersedes(earlier));
+        self.pending.push_back(command);
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        let mut audio_event_tx = self.audio_event_tx.borrow_mut();
+        while let Some(command) = self.pending.pop_front() {
+            if let Err(rtrb::PushError::Full(command)) = audio_event_tx.push(command) {
+                self.pending.push_front(command);
+                break;
+            }
         }
+        self.backlog = self.pending.len();
     }
 }
 
@@ -35,13 +192,108 @@ impl Model for MainModel {
             use AppEvent::*;
             match *app_event {
                 AddNode(kind) => {
-                    self.nodes.push(audio::Node::new(kind));
-                    self.audio_event_tx
-                        .borrow_mut()
-                        .push(Command::AddNode(0, audio::Node::new(kind)));
+                    if self.nodes.len() < audio::MAX_NODES {
+                        self.add_node(audio::Node::new(kind));
+                    } else {
+                        let error = audio::EngineError::NodeListFull(0).to_string();
+                        self.set_engine_error(audio::CommandKind::AddNode, error);
+                    }
+                }
+                AddAlgorithm(algorithm) => {
+                    let operators = algorithm.operators();
+                    if self.nodes.len() + operators > audio::MAX_NODES {
+                        let error = audio::EngineError::NodeListFull(0).to_string();
+                        self.set_engine_error(audio::CommandKind::AddNode, error);
+                    } else if let Some(first_slot) = self.free_audio_slots(operators) {
+                        let output = audio::Slot::Audio(0);
+                        let mix_in = self.writes_to(output).then_some(output);
+                        for node in algorithm.nodes(first_slot, mix_in) {
+                            self.add_node(node);
+                        }
+                    } else {
+                        let error = "There aren't enough free audio slots for the operators.";
+                        self.set_engine_error(audio::CommandKind::AddNode, error.into());
+                    }
                 }
                 RemoveNode(index) => {
                     self.nodes.remove(index);
+                    self.send(Command::RemoveNode(0, index));
+                }
+                SetPortValue(node, port, value) => {
+                    if let Some(input) = self
+                        .nodes
+                        .get_mut(node)
+                        .and_then(|node| node.inputs.get_mut(port))
+                    {
+                        input.value = value;
+                        self.send(Command::SetPortValue {
+                            channel: 0,
+                            node,
+                            port,
+                            value,
+                        });
+                    }
+                }
+                SetTempo(tempo) => {
+                    let range = audio::Range::TEMPO;
+                    self.tempo = tempo.clamp(range.min, range.max);
+                    self.send(Command::SetTempo(self.tempo));
+                }
+                ChooseAudio(index) => {
+                    self.choosing_audio = self.nodes.get(index).map(|node| node.id);
+                }
+                LoadAudio(ref path) => {
+                    let choosing = self.choosing_audio.take();
+                    if let Some((id, index)) =
+                        choosing.and_then(|id| Some((id, self.index_of(id)?)))
+                    {
+                        let request = LoadRequest(id, self.nodes[index].kind, path.clone());
+                        // The loader only stops when the model is gone.
+                        let _ = self.audio_loader.send(request);
+                    }
+                }
+                AudioLoaded(id, ref result) => match result {
+                    Ok(audio) => {
+                        // The node may have been removed while the file was loading.
+                        if let Some(index) = self.index_of(id) {
+                            let node = &mut self.nodes[index];
+                            if node.uses_audio {
+                                node.audio = Some(audio.clone());
+                                self.load_error = None;
+                                self.send(Command::SetNodeAudio {
+                                    channel: 0,
+                                    node: index,
+                                    audio: audio.clone(),
+                                });
+                            }
+                        }
+                    }
+                    Err(error) => {
+                        self.load_error = Some(error.clone());
+                    }
+                },
+                SetExpression(index, ref source) => match audio::Program::compile(source) {
+                    Ok(program) => {
+                        if let Some(node) = self.nodes.get_mut(index) {
+                            if node.uses_program {
+                                let program = audio::SharedProgram::new(&self.collector, program);
+                                node.program = Some(program.clone());
+                                self.expression_error = None;
+                                self.send(Command::SetNodeProgram {
+                                    channel: 0,
+                                    node: index,
+                                    program,
+                                });
+                            }
+                        }
+                    }
+                    Err(error) => {
+                        self.expression_error = Some(error.to_string());
+                    }
+                },
+                DismissEngineError => {
+                    self.engine_error = None;
+                    self.engine_error_from = None;
                 }
                 MidiIn(ref midi_message) => match *midi_message {
                     MidiMessage::NoteOn(_channel, note, _velocity) => {
@@ -49,6 +301,48 @@ impl Model for MainModel {
                     }
                     _ => {}
                 },
+                Feedback(ref feedback) => {
+                    match *feedback {
+                        audio::Feedback::MidiIn(_) => {}
+                        audio::Feedback::Command(kind, result) => match result {
+                            Err(error) => self.set_engine_error(kind, error.to_string()),
+                            Ok(()) if self.engine_error_from == Some(kind) => {
+                                self.engine_error = None;
+                                self.engine_error_from = None;
+                            }
+                            Ok(()) => {}
+                        },
+                        audio::Feedback::Voices(voices) => {
+                            self.voices = voices;
+                        }
+                        audio::Feedback::Meter(peak) => {
+                            self.peak = peak;
+                        }
+                        audio::Feedback::Xrun(frames) => {
+                            self.dropped_frames += frames as usize;
+                        }
+                    }
+                    // Meter readings arrive steadily, so they double as the retry timer.
+                    self.flush();
+                }
+                Notification(ref notification) => match *notification {
+                    audio::Notification::Xrun => {
+                        self.xruns += 1;
+                    }
+                    audio::Notification::SampleRate(sample_rate) => {
+                        self.sample_rate = sample_rate;
+                    }
+                    audio::Notification::BufferSize(buffer_size) => {
+                        self.buffer_size = buffer_size;
+                    }
+                    audio::Notification::Connections(port, count) => match port {
+                        audio::ClientPort::MidiIn => self.midi_connections = count,
+                        audio::ClientPort::AudioOut => self.audio_connections = count,
+                    },
+                    audio::Notification::Shutdown(ref reason) => {
+                        self.server_gone = Some(reason.clone());
+                    }
+                },
             }
         }
     }
@@ -57,8 +351,34 @@ impl Model for MainModel {
 #[derive(Clone, Debug)]
 pub enum AppEvent {
     AddNode(audio::NodeKind),
+    /// Adds a pre-wired group of FM operators.
+    AddAlgorithm(&'static audio::Algorithm),
     RemoveNode(usize),
+    /// Node index, input index and the new value.
+    SetPortValue(usize, usize, f32),
+    /// Beats per minute, clamped to the range the engine accepts.
+    SetTempo(f32),
+    /// Opens the file picker for the node at this index.
+    ChooseAudio(usize),
+    /// Loads a file into the node the picker was opened for.
+    LoadAudio(PathBuf),
+    /// A file finished loading for the node with this ID.
+    AudioLoaded(NodeId, Result<audio::SharedAudio, String>),
+    /// Compiles a formula for the expression node at this index.
+    SetExpression(usize, String),
+    DismissEngineError,
     MidiIn(wmidi::MidiMessage<'static>),
+    Feedback(audio::Feedback),
+    Notification(audio::Notification),
+}
+
+impl From<audio::Feedback> for AppEvent {
+    fn from(feedback: audio::Feedback) -> AppEvent {
+        match feedback {
+            audio::Feedback::MidiIn(midi_message) => AppEvent::MidiIn(midi_message),
+            feedback => AppEvent::Feedback(feedback),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
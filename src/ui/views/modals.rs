@@ -1,7 +1,9 @@
+use std::path::PathBuf;
+
 use crate::audio;
 use vizia::*;
 
-use crate::ui::AppEvent;
+use crate::ui::{AppEvent, MainModel};
 
 pub fn build(cx: &mut Context) {
     ModalManager::default().build(cx);
@@ -20,6 +22,17 @@ pub fn build(cx: &mut Context) {
                         move |cx| Label::new(cx, node.name()),
                     );
                 }
+                Label::new(cx, "FM algorithms").class("heading");
+                for algorithm in &audio::ALGORITHMS {
+                    Button::new(
+                        cx,
+                        move |cx| {
+                            cx.emit(AppEvent::AddAlgorithm(algorithm));
+                            cx.emit(ModalEvent::Hide);
+                        },
+                        move |cx| Label::new(cx, algorithm.name),
+                    );
+                }
             });
         },
         |cx| {
@@ -31,7 +44,82 @@ pub fn build(cx: &mut Context) {
                 |cx| Label::new(cx, "Close"),
             );
         },
-    )
+    );
+    modal(
+        cx,
+        "load audio",
+        |cx| {
+            VStack::new(cx, |cx| {
+                let files = audio_files();
+                if files.is_empty() {
+                    Label::new(cx, &format!("No WAV files in {}/", SAMPLES_DIR));
+                }
+                for path in files {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    let name = name.into_owned();
+                    Button::new(
+                        cx,
+                        move |cx| {
+                            cx.emit(AppEvent::LoadAudio(path.clone()));
+                            cx.emit(ModalEvent::Hide);
+                        },
+                        move |cx| Label::new(cx, &name),
+                    );
+                }
+            });
+        },
+        |cx| {
+            Button::new(
+                cx,
+                |cx| {
+                    cx.emit(ModalEvent::Hide);
+                },
+                |cx| Label::new(cx, "Close"),
+            );
+        },
+    );
+    server_gone(cx);
+}
+
+/// Where the file picker looks for audio, relative to the working directory like the
+/// stylesheet.
+const SAMPLES_DIR: &str = "samples";
+
+/// WAV files in `SAMPLES_DIR`, by name. The modal is rebuilt each time it's shown, so this
+/// picks up new files.
+fn audio_files() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(SAMPLES_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension.eq_ignore_ascii_case("wav"))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Shown once JACK shuts us down. There's nothing to go back to, so it can't be closed.
+fn server_gone(cx: &mut Context) {
+    Binding::new(cx, MainModel::server_gone, |cx, reason| {
+        let reason = reason.get(cx).clone();
+        let display = if reason.is_some() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+        VStack::new(cx, move |cx| {
+            Label::new(cx, "The JACK server has shut down.");
+            if let Some(reason) = &reason {
+                Label::new(cx, reason);
+            }
+        })
+        .class("modal")
+        .display(display);
+    });
 }
 
 fn modal(
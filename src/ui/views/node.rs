@@ -1,17 +1,14 @@
 use vizia::*;
 
-use crate::{
-    audio,
-    ui::{AppEvent, MainModel},
-};
+use crate::ui::{views::ModalEvent, AppEvent, MainModel, NodeInfo};
 
 pub struct Node {
-    data: audio::Node,
+    data: NodeInfo,
     index: usize,
 }
 
 impl Node {
-    pub fn new(cx: &mut Context, data: audio::Node, index: usize) -> Handle<Self> {
+    pub fn new(cx: &mut Context, data: NodeInfo, index: usize) -> Handle<Self> {
         Node { data, index }.build(cx)
     }
 }
@@ -19,17 +16,64 @@ impl Node {
 impl View for Node {
     fn body(&mut self, cx: &mut Context) {
         let index = self.index;
-        let mut node = self.data;
+        let node = self.data.clone();
         HStack::new(cx, move |cx| {
-            for input in node.inputs() {
-                Label::new(cx, input.name).class("input");
-                Knob::new(cx, 0.0, StaticLens::new(&0.5), true).on_changing(move |knob, cx| {
-                    println!("{}", knob.current);
-                });
+            for (port, input) in node.inputs.iter().enumerate() {
+                let (name, range, value) = (input.name, input.range, input.value);
+                VStack::new(cx, move |cx| {
+                    PortKnob {
+                        normalized: range.normalize(value),
+                    }
+                    .build(cx);
+                    Label::new(cx, name).class("input");
+                    let centred = range.min < 0.0 && range.max > 0.0;
+                    Knob::new(
+                        cx,
+                        range.normalize(range.default),
+                        PortKnob::normalized,
+                        centred,
+                    )
+                    .on_changing(move |knob, cx| {
+                        cx.emit(PortKnobEvent(knob.current));
+                        cx.emit(AppEvent::SetPortValue(
+                            index,
+                            port,
+                            range.denormalize(knob.current),
+                        ));
+                    });
+                    Binding::new(cx, PortKnob::normalized, move |cx, normalized| {
+                        let value = range.denormalize(*normalized.get(cx));
+                        Label::new(cx, &range.display(value)).class("value");
+                    });
+                })
+                .class("port");
             }
-            for output in node.outputs() {
+            for output in node.outputs.iter() {
                 Label::new(cx, output.name).class("output");
             }
+            if node.uses_audio {
+                let file = node.audio.as_ref().map(|audio| audio.name.clone());
+                VStack::new(cx, move |cx| {
+                    Label::new(cx, file.as_deref().unwrap_or("No file"));
+                    Button::new(
+                        cx,
+                        move |cx| {
+                            cx.emit(AppEvent::ChooseAudio(index));
+                            cx.emit(ModalEvent::Show("load audio"));
+                        },
+                        |cx| Label::new(cx, "Load"),
+                    );
+                })
+                .class("audio-file");
+            }
+            if node.uses_program {
+                let source = node.program.as_ref().map(|program| program.source.clone());
+                Textbox::new(cx, source.as_deref().unwrap_or(""))
+                    .on_submit(move |cx, text| {
+                        cx.emit(AppEvent::SetExpression(index, text.to_owned()));
+                    })
+                    .class("expression");
+            }
             Button::new(
                 cx,
                 move |cx| {
@@ -47,3 +91,21 @@ impl View for Node {
         .class("node");
     }
 }
+
+/// Where one input's knob is, so the knob and its readout agree.
+#[derive(Lens)]
+struct PortKnob {
+    normalized: f32,
+}
+
+impl Model for PortKnob {
+    fn event(&mut self, cx: &mut Context, event: &mut Event) {
+        let _ = cx;
+        if let Some(PortKnobEvent(normalized)) = event.message.downcast() {
+            self.normalized = *normalized;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PortKnobEvent(f32);
@@ -7,7 +7,7 @@ pub fn build(cx: &mut Context) {
         List::new(cx, MainModel::nodes, |cx, index, node| {
             let data = node.get(cx).clone();
             HStack::new(cx, move |cx| {
-                Label::new(cx, node.get(cx).name());
+                Label::new(cx, node.get(cx).kind.name());
                 views::Node::new(cx, data, index);
             })
             .height(Auto);